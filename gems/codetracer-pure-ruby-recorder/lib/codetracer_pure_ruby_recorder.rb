@@ -296,9 +296,7 @@ module CodeTracer
 
     def record_call(tp)
       if self.tracks_call?(tp)
-        module_name = tp.self.class.name
-        method_name_prefix = module_name == 'Object' ? '' :  "#{module_name}#"
-        method_name = "#{method_name_prefix}#{tp.method_id}"
+        method_name = qualified_method_name(tp)
         if @debug
           codetracer_original_puts "call #{method_name} with #{tp.parameters}"
         end
@@ -331,6 +329,26 @@ module CodeTracer
       end
     end
 
+    # Name a method after the module that defines it rather than the
+    # receiver's class: `Owner#name` for instance methods (including ones
+    # mixed in or inherited), `Owner.name` for singleton methods, and the
+    # bare name for top-level methods defined on Object.
+    def qualified_method_name(tp)
+      owner = tp.defined_class
+      return tp.method_id.to_s if owner.nil? || owner == Object
+
+      if owner.singleton_class?
+        attached = owner.attached_object
+        owner_name = attached.is_a?(Module) ? attached.name : attached.class.name
+        separator = '.'
+      else
+        owner_name = owner.name
+        separator = '#'
+      end
+      owner_name ||= tp.self.class.name
+      "#{owner_name}#{separator}#{tp.method_id}"
+    end
+
     def record_return(tp)
       if self.tracks_call?(tp) && @call_depth > 0
         @call_depth -= 1
@@ -1,37 +1,55 @@
 #![allow(clippy::missing_safety_doc)]
 
+use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::Mutex;
 use std::{
+    collections::{hash_map::DefaultHasher, HashMap, HashSet},
     ffi::CStr,
+    hash::{Hash, Hasher},
     mem::transmute,
     os::raw::{c_char, c_int, c_void},
     path::Path,
     ptr,
+    rc::Rc,
     string::FromUtf8Error,
 };
 
 use codetracer_trace_types::{
-    EventLogKind, FullValueRecord, Line, TypeId, TypeKind, ValueRecord, NONE_TYPE_ID,
+    EventLogKind, FieldTypeRecord, FullValueRecord, Line, TypeId, TypeKind, TypeRecord,
+    TypeSpecificInfo, ValueRecord, NONE_TYPE_ID,
 };
 use codetracer_trace_writer_nim::{
     create_trace_writer, trace_writer::TraceWriter, StreamingValueEncoder, TraceEventsFileFormat,
 };
 use rb_sys::{
-    rb_add_event_hook2, rb_cObject, rb_cRange, rb_cRegexp, rb_cStruct, rb_cThread, rb_cTime,
-    rb_check_typeddata, rb_const_defined, rb_const_get, rb_data_type_struct__bindgen_ty_1,
-    rb_data_type_t, rb_data_typed_object_wrap, rb_define_alloc_func, rb_define_class,
-    rb_define_method, rb_eIOError, rb_eval_string, rb_event_flag_t, rb_event_hook_flag_t,
-    rb_event_hook_func_t, rb_funcall, rb_id2name, rb_id2sym, rb_intern,
-    rb_internal_thread_add_event_hook, rb_internal_thread_event_data_t, rb_method_boundp,
-    rb_num2dbl, rb_num2long, rb_obj_classname, rb_obj_is_kind_of, rb_protect, rb_raise,
-    rb_remove_event_hook_with_data, rb_set_errinfo, rb_sym2id, rb_trace_arg_t, rb_tracearg_binding,
-    rb_tracearg_callee_id, rb_tracearg_event_flag, rb_tracearg_lineno, rb_tracearg_path,
-    rb_tracearg_raised_exception, rb_tracearg_return_value, rb_tracearg_self, Qfalse, Qnil, Qtrue,
-    ID, NIL_P, RARRAY_CONST_PTR, RARRAY_LEN, RB_FLOAT_TYPE_P, RB_INTEGER_TYPE_P, RB_SYMBOL_P,
-    RB_TYPE_P, RSTRING_LEN, RSTRING_PTR, RUBY_EVENT_CALL, RUBY_EVENT_LINE, RUBY_EVENT_RAISE,
-    RUBY_EVENT_RETURN, RUBY_INTERNAL_THREAD_EVENT_EXITED, RUBY_INTERNAL_THREAD_EVENT_READY,
-    RUBY_INTERNAL_THREAD_EVENT_RESUMED, RUBY_INTERNAL_THREAD_EVENT_STARTED,
-    RUBY_INTERNAL_THREAD_EVENT_SUSPENDED, VALUE,
+    rb_add_event_hook2, rb_any_to_s, rb_ary_clear, rb_ary_delete, rb_ary_entry, rb_ary_includes,
+    rb_ary_new, rb_ary_new_capa, rb_ary_push, rb_ascii8bit_encoding, rb_attr_get, rb_autoload_p,
+    rb_big2str, rb_cEnumerator, rb_cIO, rb_cObject, rb_cRange, rb_cRegexp, rb_cStruct, rb_cThread,
+    rb_cTime, rb_check_typeddata, rb_class_attached_object, rb_class_name, rb_class_superclass,
+    rb_complex_imag, rb_complex_real, rb_const_defined, rb_const_get,
+    rb_data_type_struct__bindgen_ty_1, rb_data_type_t, rb_data_typed_object_wrap,
+    rb_define_alloc_func, rb_define_class, rb_define_method, rb_define_singleton_method,
+    rb_eArgError, rb_eException, rb_eIOError, rb_eTypeError, rb_enc_get, rb_enc_str_coderange,
+    rb_encoding, rb_event_flag_t, rb_event_hook_flag_t, rb_event_hook_func_t, rb_funcall,
+    rb_funcallv, rb_gc_mark, rb_hash_aref, rb_hash_aset, rb_hash_clear, rb_hash_foreach,
+    rb_hash_lookup2, rb_id2name, rb_id2sym, rb_ident_hash_new, rb_int2inum, rb_intern,
+    rb_internal_thread_add_event_hook, rb_internal_thread_event_data_t, rb_io_descriptor,
+    rb_ivar_foreach, rb_ivar_set, rb_mod_ancestors, rb_mod_name, rb_num2dbl, rb_num2long,
+    rb_obj_class, rb_obj_classname, rb_obj_id, rb_obj_is_kind_of, rb_obj_is_method, rb_obj_is_proc,
+    rb_proc_arity, rb_proc_lambda_p, rb_protect, rb_raise, rb_range_values, rb_rational_den,
+    rb_rational_num, rb_reg_options, rb_remove_event_hook_with_data, rb_set_errinfo,
+    rb_str_conv_enc, rb_str_new_cstr, rb_str_strlen, rb_str_substr, rb_struct_getmember,
+    rb_struct_members, rb_sym2id, rb_thread_add_event_hook2, rb_thread_current, rb_thread_main,
+    rb_thread_remove_event_hook_with_data, rb_time_timespec, rb_time_utc_offset, rb_trace_arg_t,
+    rb_tracearg_binding, rb_tracearg_callee_id, rb_tracearg_defined_class, rb_tracearg_event_flag,
+    rb_tracearg_lineno, rb_tracearg_method_id, rb_tracearg_parameters, rb_tracearg_path,
+    rb_tracearg_raised_exception, rb_tracearg_return_value, rb_tracearg_self, rb_usascii_encoding,
+    rb_utf8_encoding, st_data_t, Qfalse, Qnil, Qtrue, RBasic, RTypedData, FIXNUM_P, ID, NIL_P,
+    RARRAY_CONST_PTR, RARRAY_LEN, RB_FLOAT_TYPE_P, RB_INTEGER_TYPE_P, RB_SYMBOL_P, RB_TYPE_P,
+    RHASH_SIZE, RSTRING_LEN, RSTRING_PTR, RTYPEDDATA_P, RUBY_EVENT_B_CALL, RUBY_EVENT_B_RETURN,
+    RUBY_EVENT_CALL, RUBY_EVENT_CLASS, RUBY_EVENT_C_CALL, RUBY_EVENT_C_RETURN, RUBY_EVENT_END,
+    RUBY_EVENT_LINE, RUBY_EVENT_RAISE, RUBY_EVENT_RESCUE, RUBY_EVENT_RETURN,
+    RUBY_INTERNAL_THREAD_EVENT_EXITED, RUBY_INTERNAL_THREAD_EVENT_STARTED, TEST, VALUE,
 };
 
 #[cfg(test)]
@@ -133,23 +151,48 @@ struct InternedSymbols {
     to_s: ID,
     local_variables: ID,
     local_variable_get: ID,
-    instance_method: ID,
-    parameters: ID,
-    class: ID,
     to_a: ID,
-    begin: ID,
-    end: ID,
-    to_i: ID,
-    nsec: ID,
+    first: ID,
     source: ID,
-    options: ID,
-    members: ID,
-    values: ID,
     to_h: ID,
-    instance_variables: ID,
-    instance_variable_get: ID,
     set_const: ID,
+    big_decimal_const: ID,
+    data_const: ID,
+    compare_by_identity_p: ID,
+    default_proc: ID,
+    precision: ID,
     open_struct_const: ID,
+    date_const: ID,
+    date_time_const: ID,
+    active_support_const: ID,
+    time_with_zone_const: ID,
+    pathname_const: ID,
+    uri_const: ID,
+    generic_const: ID,
+    ip_addr_const: ID,
+    iso8601: ID,
+    to_i: ID,
+    nsec: ID,
+    utc_offset: ID,
+    zone: ID,
+    prefix: ID,
+    backtrace_locations: ID,
+    path: ID,
+    lineno: ID,
+    cause: ID,
+    mesg: ID,
+    source_location: ID,
+    name: ID,
+    owner: ID,
+    arity: ID,
+    call: ID,
+    size: ID,
+    pos: ID,
+    param_rest: ID,
+    param_keyreq: ID,
+    param_key: ID,
+    param_keyrest: ID,
+    param_block: ID,
 }
 
 impl InternedSymbols {
@@ -158,35 +201,341 @@ impl InternedSymbols {
             to_s: rb_intern!("to_s"),
             local_variables: rb_intern!("local_variables"),
             local_variable_get: rb_intern!("local_variable_get"),
-            instance_method: rb_intern!("instance_method"),
-            parameters: rb_intern!("parameters"),
-            class: rb_intern!("class"),
             to_a: rb_intern!("to_a"),
-            begin: rb_intern!("begin"),
-            end: rb_intern!("end"),
-            to_i: rb_intern!("to_i"),
-            nsec: rb_intern!("nsec"),
+            first: rb_intern!("first"),
             source: rb_intern!("source"),
-            options: rb_intern!("options"),
-            members: rb_intern!("members"),
-            values: rb_intern!("values"),
             to_h: rb_intern!("to_h"),
-            instance_variables: rb_intern!("instance_variables"),
-            instance_variable_get: rb_intern!("instance_variable_get"),
             set_const: rb_intern!("Set"),
+            big_decimal_const: rb_intern!("BigDecimal"),
+            data_const: rb_intern!("Data"),
+            compare_by_identity_p: rb_intern!("compare_by_identity?"),
+            default_proc: rb_intern!("default_proc"),
+            precision: rb_intern!("precision"),
             open_struct_const: rb_intern!("OpenStruct"),
+            date_const: rb_intern!("Date"),
+            date_time_const: rb_intern!("DateTime"),
+            active_support_const: rb_intern!("ActiveSupport"),
+            time_with_zone_const: rb_intern!("TimeWithZone"),
+            pathname_const: rb_intern!("Pathname"),
+            uri_const: rb_intern!("URI"),
+            generic_const: rb_intern!("Generic"),
+            ip_addr_const: rb_intern!("IPAddr"),
+            iso8601: rb_intern!("iso8601"),
+            to_i: rb_intern!("to_i"),
+            nsec: rb_intern!("nsec"),
+            utc_offset: rb_intern!("utc_offset"),
+            zone: rb_intern!("zone"),
+            prefix: rb_intern!("prefix"),
+            backtrace_locations: rb_intern!("backtrace_locations"),
+            path: rb_intern!("path"),
+            lineno: rb_intern!("lineno"),
+            cause: rb_intern!("cause"),
+            mesg: rb_intern!("mesg"),
+            source_location: rb_intern!("source_location"),
+            name: rb_intern!("name"),
+            owner: rb_intern!("owner"),
+            arity: rb_intern!("arity"),
+            call: rb_intern!("call"),
+            size: rb_intern!("size"),
+            pos: rb_intern!("pos"),
+            param_rest: rb_intern!("rest"),
+            param_keyreq: rb_intern!("keyreq"),
+            param_key: rb_intern!("key"),
+            param_keyrest: rb_intern!("keyrest"),
+            param_block: rb_intern!("block"),
+        }
+    }
+}
+
+/// Recording options passed from Ruby as the optional third argument of
+/// `CodeTracerNativeRecorder.new(out_dir, format, options)`.  Every option
+/// defaults to the behaviour the recorder had before it was introduced, so
+/// callers that pass no options get an unchanged trace shape.
+struct RecorderOptions {
+    /// Also record calls into C-implemented methods (`RUBY_EVENT_C_CALL` /
+    /// `RUBY_EVENT_C_RETURN`), e.g. `Array#map` or `Integer#times`.
+    c_calls: bool,
+    /// Record every block / lambda invocation as its own frame
+    /// (`RUBY_EVENT_B_CALL` / `RUBY_EVENT_B_RETURN`), named after the
+    /// method that defines the block, e.g. `block in Foo#bar`.
+    blocks: bool,
+    /// Record the execution of `class Foo ... end` / `module Bar ... end`
+    /// bodies as `<class:Foo>` / `<module:Bar>` frames (`RUBY_EVENT_CLASS`
+    /// / `RUBY_EVENT_END`) instead of folding their steps into the caller.
+    class_bodies: bool,
+    /// Track the full exception lifecycle: encode raised exceptions
+    /// structurally as `<exception>`, record where they are rescued
+    /// (`RUBY_EVENT_RESCUE`) and mark the frames they unwind.
+    exceptions: bool,
+    /// Tag every encoded heap object with its `object_id` so aliasing is
+    /// visible across variables, and encode repeats within one value as
+    /// back-references.
+    object_ids: bool,
+    /// Read object state only through C APIs (`rb_ivar_foreach`,
+    /// `rb_struct_getmember`, `rb_hash_foreach`, ...) and never call back
+    /// into Ruby methods of the recorded values, so recording cannot change
+    /// what the program does.  Values whose state is only reachable through
+    /// method calls are encoded as their default `#<Class:0x...>` form.
+    pure_capture: bool,
+    /// Encode the receiver registered as `self` at each call with the full
+    /// value encoder (instance variables, struct members, ...) instead of
+    /// its `to_s` text.
+    structured_self: bool,
+    /// Register a local at a line step only when its encoded value differs
+    /// from the last one registered in the same frame; the names carried
+    /// over unchanged are listed in an `<unchanged>` variable.
+    incremental_locals: bool,
+    /// Nesting levels expanded per value (at most `MAX_COMPOUND_NESTING`);
+    /// deeper objects are written as `<depth-limit>` with their class name.
+    max_depth: usize,
+    /// Elements encoded per Array, Hash or Set.
+    max_elements: usize,
+    /// Bytes encoded per String.
+    max_string_bytes: usize,
+    /// Which files and frames are recorded (`include` / `exclude`).
+    filter: FilterRules,
+}
+
+impl Default for RecorderOptions {
+    fn default() -> RecorderOptions {
+        RecorderOptions {
+            c_calls: false,
+            blocks: false,
+            class_bodies: false,
+            exceptions: false,
+            object_ids: false,
+            pure_capture: false,
+            structured_self: false,
+            incremental_locals: false,
+            max_depth: DEFAULT_MAX_DEPTH,
+            max_elements: usize::MAX,
+            max_string_bytes: usize::MAX,
+            filter: FilterRules::default(),
+        }
+    }
+}
+
+/// Look up `options[:key]`, returning `nil` when the key is absent.
+unsafe fn option_value(options: VALUE, key: ID) -> VALUE {
+    rb_hash_aref(options, rb_id2sym(key))
+}
+
+/// `options[:key]` as a count in `1..=max`, or `default` when absent.
+unsafe fn limit_option(options: VALUE, key: ID, default: usize, max: usize) -> usize {
+    let value = option_value(options, key);
+    if NIL_P(value) {
+        return default;
+    }
+    let limit = rb_num2long(value);
+    if limit < 1 || limit as u64 > max as u64 {
+        rb_raise(
+            rb_eArgError,
+            c"capture limit %s must be between 1 and %lu".as_ptr() as *const c_char,
+            rb_id2name(key),
+            max as std::os::raw::c_ulong,
+        );
+    }
+    limit as usize
+}
+
+/// `options[:key]` as a list of `FilterRule`s (an Array of Strings), or
+/// none when absent.
+unsafe fn filter_rules_option(options: VALUE, key: ID) -> Vec<FilterRule> {
+    let value = option_value(options, key);
+    if NIL_P(value) {
+        return Vec::new();
+    }
+    if !RB_TYPE_P(value, rb_sys::ruby_value_type::RUBY_T_ARRAY) {
+        rb_raise(
+            rb_eArgError,
+            c"%s rules must be an Array of Strings".as_ptr() as *const c_char,
+            rb_id2name(key),
+        );
+    }
+    let len = RARRAY_LEN(value) as usize;
+    let ptr = RARRAY_CONST_PTR(value);
+    let mut rules = Vec::with_capacity(len);
+    for i in 0..len {
+        let entry = *ptr.add(i);
+        let text = if RB_TYPE_P(entry, rb_sys::ruby_value_type::RUBY_T_STRING) {
+            rstring_checked(entry).ok()
+        } else {
+            None
+        };
+        let Some(text) = text else {
+            rb_raise(
+                rb_eArgError,
+                c"%s rules must be an Array of Strings".as_ptr() as *const c_char,
+                rb_id2name(key),
+            );
+        };
+        rules.push(FilterRule::parse(&text));
+    }
+    rules
+}
+
+impl RecorderOptions {
+    /// Read the options hash.  `nil` yields the defaults; unknown keys are
+    /// ignored so newer Ruby wrappers keep working against older builds.
+    unsafe fn from_hash(options: VALUE) -> RecorderOptions {
+        let mut result = RecorderOptions::default();
+        if NIL_P(options) {
+            return result;
+        }
+        if !RB_TYPE_P(options, rb_sys::ruby_value_type::RUBY_T_HASH) {
+            rb_raise(
+                rb_eArgError,
+                c"recorder options must be a Hash".as_ptr() as *const c_char,
+            );
+        }
+        result.c_calls = TEST(option_value(options, rb_intern!("c_calls")));
+        result.blocks = TEST(option_value(options, rb_intern!("blocks")));
+        result.class_bodies = TEST(option_value(options, rb_intern!("class_bodies")));
+        result.exceptions = TEST(option_value(options, rb_intern!("exceptions")));
+        result.object_ids = TEST(option_value(options, rb_intern!("object_ids")));
+        result.pure_capture = TEST(option_value(options, rb_intern!("pure_capture")));
+        result.structured_self = TEST(option_value(options, rb_intern!("structured_self")));
+        result.incremental_locals = TEST(option_value(options, rb_intern!("incremental_locals")));
+        result.max_depth = limit_option(
+            options,
+            rb_intern!("max_depth"),
+            DEFAULT_MAX_DEPTH,
+            MAX_COMPOUND_NESTING,
+        );
+        result.max_elements =
+            limit_option(options, rb_intern!("max_elements"), usize::MAX, usize::MAX);
+        result.max_string_bytes = limit_option(
+            options,
+            rb_intern!("max_string_bytes"),
+            usize::MAX,
+            usize::MAX,
+        );
+        result.filter.include = filter_rules_option(options, rb_intern!("include"));
+        result
+            .filter
+            .exclude
+            .extend(filter_rules_option(options, rb_intern!("exclude")));
+        result.filter.frame_rules = result
+            .filter
+            .include
+            .iter()
+            .chain(&result.filter.exclude)
+            .any(|rule| matches!(rule, FilterRule::Method(_)));
+        result
+    }
+
+    /// The set of VM events `event_hook_raw` subscribes to: the recorded
+    /// ones, plus C function and block frames for `exceptions`, which
+    /// `track_unwinding` needs to tell where an exception is rescued.
+    fn event_mask(&self) -> rb_event_flag_t {
+        let mut events = self.recorded_events();
+        if self.exceptions {
+            events |=
+                RUBY_EVENT_C_CALL | RUBY_EVENT_C_RETURN | RUBY_EVENT_B_CALL | RUBY_EVENT_B_RETURN;
         }
+        events
+    }
+
+    /// The set of VM events that end up in the trace.
+    fn recorded_events(&self) -> rb_event_flag_t {
+        let mut events = RUBY_EVENT_LINE | RUBY_EVENT_CALL | RUBY_EVENT_RETURN | RUBY_EVENT_RAISE;
+        if self.c_calls {
+            events |= RUBY_EVENT_C_CALL | RUBY_EVENT_C_RETURN;
+        }
+        if self.blocks {
+            events |= RUBY_EVENT_B_CALL | RUBY_EVENT_B_RETURN;
+        }
+        if self.class_bodies {
+            events |= RUBY_EVENT_CLASS | RUBY_EVENT_END;
+        }
+        if self.exceptions {
+            events |= RUBY_EVENT_RESCUE;
+        }
+        events
     }
 }
 
 struct RecorderData {
+    /// Some hook is installed: the process-wide one or per-thread ones.
     active: bool,
+    /// The process-wide hook from `enable_tracing` is installed.
+    global_hook: bool,
+    /// Array of the threads recorded through their own hook
+    /// (`enable_tracing_on_thread`), kept alive by the recorder object.
+    /// Their hooks are detached while `global_hook` is set.
+    traced_threads: VALUE,
+    /// The `<top-level>` call is open: from `initialize` until tracing
+    /// stops, then again from the next start.
+    top_level_open: bool,
     in_event_hook: bool,
     thread_event_hook_installed: bool,
+    /// Recorder-assigned number (see `ThreadNumbers`) of the thread that
+    /// produced the last recorded event.
     last_thread_id: Option<u64>,
+    /// Last name announced for each thread number; threads can be renamed
+    /// while they run.
+    thread_names: HashMap<u64, String>,
+    /// Shadow call stacks: per thread number (see `ThreadNumbers`), the
+    /// frames the thread opened with `register_call` since tracing was
+    /// enabled, not counting `<top-level>`.  Return events for frames a
+    /// thread entered before tracing started are dropped and frames still
+    /// open when tracing stops are closed on their own thread, so every
+    /// call the writer sees gets exactly one return.  Threads without
+    /// open frames have no entry.
+    open_frames: HashMap<u64, usize>,
+    /// With `method:` rules: per thread number, whether each frame entered
+    /// since tracing started is recorded, innermost last (see
+    /// `frame_event_traced`).  Threads without open frames have no entry,
+    /// and a thread's entry is dropped when it exits.
+    frame_filter: HashMap<u64, Vec<bool>>,
+    /// `incremental_locals` shadow: per thread number, for each of the
+    /// thread's open frames (indexed by its count in `open_frames`,
+    /// outermost first), a hash of the CBOR bytes last registered for each
+    /// local.
+    frame_locals: HashMap<u64, Vec<HashMap<String, u64>>>,
+    /// Frame depth and unwinding state for `exceptions` per thread number,
+    /// kept for every event, traced or not (see `track_unwinding`).
+    /// Threads back at their starting depth with nothing unwinding have no
+    /// entry, and a thread's entry is dropped when it exits.
+    thread_frames: HashMap<u64, ThreadFrames>,
+    options: RecorderOptions,
     id: InternedSymbols,
     set_class: VALUE,
+    big_decimal_class: VALUE,
+    data_class: VALUE,
+    /// Struct types registered per Ruby class name, one per field layout
+    /// (see `ensure_struct_type_id`).
+    struct_types: HashMap<String, Vec<(Vec<String>, TypeId)>>,
+    /// Heap objects currently being encoded, root first.
+    encoding_path: Vec<VALUE>,
+    /// Heap objects already encoded in the current value (`object_ids`).
+    encoded_objects: HashSet<VALUE>,
+    /// Compounds open around the heap object being encoded (see
+    /// `LEVEL_COMPOUNDS`).
+    open_compounds: usize,
     open_struct_class: VALUE,
+    date_class: VALUE,
+    date_time_class: VALUE,
+    time_with_zone_class: VALUE,
+    pathname_class: VALUE,
+    uri_class: VALUE,
+    ip_addr_class: VALUE,
+    /// Source files seen by `event_hook_raw`, keyed on the path String the
+    /// VM reports (one object per iseq): the path as text, or `None` when
+    /// the `include` / `exclude` rules filter the file.
+    paths: PathCache,
+    /// Array holding the keys of `paths`.  `recorder_mark` pins it and its
+    /// elements, so the GC can neither free nor move (`GC.compact`) a key
+    /// and hand its address to another String.
+    path_values: VALUE,
+    /// Identity Hash of Class / Module => serializer registered with
+    /// `register_serializer`, or `nil` while there are none.
+    serializers: VALUE,
+    /// Identity Hash of named Class => `[serializer, generation]`: the
+    /// serializer resolved for its instances (`nil` for none) and the
+    /// `ANCESTRY_GENERATION` it was resolved at, filled lazily by
+    /// `serializer_for`.
+    serializer_cache: VALUE,
     int_type_id: TypeId,
     float_type_id: TypeId,
     bool_type_id: TypeId,
@@ -195,9 +544,70 @@ struct RecorderData {
     error_type_id: TypeId,
 }
 
+/// Small, stable thread numbers handed to the trace writer instead of
+/// `Thread` object addresses: 1 for the main thread, taken when the
+/// recorder is created, then 2, 3, ... for the other threads in the order
+/// they are seen.  A number is released when its thread exits, so an
+/// address reused by a later thread gets a fresh one.
+#[derive(Default)]
+struct ThreadNumbers {
+    numbers: HashMap<VALUE, u64>,
+    last_assigned: u64,
+}
+
+impl ThreadNumbers {
+    fn number(&mut self, thread: VALUE) -> u64 {
+        *self.numbers.entry(thread).or_insert_with(|| {
+            self.last_assigned += 1;
+            self.last_assigned
+        })
+    }
+
+    /// Forget `thread`, returning its number; `None` for a thread that
+    /// never got one.
+    fn release(&mut self, thread: VALUE) -> Option<u64> {
+        self.numbers.remove(&thread)
+    }
+}
+
+/// A thread start or exit reported by `ex_callback`.
+enum ThreadEvent {
+    Started(u64),
+    Exited(u64),
+}
+
+/// Write the thread events `ex_callback` queued since the last call, and
+/// drop the state kept for the threads that exited: `ex_callback` can run
+/// without the GVL, so it leaves `RecorderData` alone.
+fn write_thread_events(
+    pending: &Mutex<Vec<ThreadEvent>>,
+    data: &mut RecorderData,
+    tracer: &mut dyn TraceWriter,
+) {
+    for event in pending.lock().unwrap().drain(..) {
+        match event {
+            ThreadEvent::Started(thread_id) => {
+                TraceWriter::register_thread_start(tracer, thread_id)
+            }
+            ThreadEvent::Exited(thread_id) => {
+                data.frame_filter.remove(&thread_id);
+                data.thread_frames.remove(&thread_id);
+                TraceWriter::register_thread_exit(tracer, thread_id)
+            }
+        }
+    }
+}
+
 struct Recorder {
     tracer: Mutex<Box<dyn TraceWriter>>,
     data: RecorderData,
+    /// Shared with the internal thread event hook, which can run without
+    /// the GVL.
+    threads: Mutex<ThreadNumbers>,
+    /// Thread starts and exits waiting for the next holder of `tracer`.
+    /// `ex_callback` never takes `tracer` itself: it is held while
+    /// serializers run user code, which may start or join threads.
+    thread_events: Mutex<Vec<ThreadEvent>>,
     out_dir: String,
     /// Reusable streaming CBOR encoder — avoids building intermediate
     /// `ValueRecord` trees when encoding Ruby values.  Reset between
@@ -205,18 +615,196 @@ struct Recorder {
     streaming_encoder: StreamingValueEncoder,
 }
 
-fn should_ignore_path(path: &str) -> bool {
-    const PATTERNS: [&str; 5] = [
-        "codetracer_ruby_recorder.rb",
-        "lib/ruby",
-        "recorder.rb",
-        "codetracer_pure_ruby_recorder.rb",
-        "gems/",
-    ];
-    if path.starts_with("<internal:") {
-        return true;
+/// Files left out unless an include rule matches them: VM internals, the
+/// recorders' own sources, the standard library and installed gems.
+const DEFAULT_EXCLUDES: [&str; 4] = ["<internal:*", "*recorder.rb", "lib/ruby/**", "gems/**"];
+
+/// One include / exclude rule, written as `gem:<glob>` (matched against
+/// the name of the installed gem a file belongs to), `method:<glob>`
+/// (matched against frame names such as `Foo::Bar#baz`,
+/// `block in Foo#baz` or `<class:Foo>`) or a plain path glob.
+enum FilterRule {
+    Path(String),
+    Gem(String),
+    Method(String),
+}
+
+impl FilterRule {
+    fn parse(text: &str) -> FilterRule {
+        if let Some(glob) = text.strip_prefix("gem:") {
+            FilterRule::Gem(glob.to_string())
+        } else if let Some(glob) = text.strip_prefix("method:") {
+            FilterRule::Method(glob.to_string())
+        } else {
+            FilterRule::Path(text.to_string())
+        }
+    }
+
+    fn matches_path(&self, path: &str) -> bool {
+        match self {
+            FilterRule::Path(glob) => path_glob_match(glob, path),
+            FilterRule::Gem(glob) => gem_name(path).is_some_and(|name| glob_match(glob, name)),
+            FilterRule::Method(_) => false,
+        }
+    }
+
+    fn matches_frame(&self, name: &str) -> bool {
+        match self {
+            FilterRule::Method(glob) => glob_match(glob, name),
+            _ => false,
+        }
+    }
+}
+
+/// Include / exclude rules.  A file or frame is recorded when an include
+/// rule matches it or no exclude rule does, so include rules carve
+/// exceptions out of the excludes (e.g. `gem:rack` out of `gems/**`).
+/// Path and gem rules filter whole files; method rules then filter frames
+/// of the remaining files.
+struct FilterRules {
+    include: Vec<FilterRule>,
+    exclude: Vec<FilterRule>,
+    /// Some rule is a `method:` rule, so frames have to be tracked.
+    frame_rules: bool,
+}
+
+impl Default for FilterRules {
+    fn default() -> FilterRules {
+        FilterRules {
+            include: Vec::new(),
+            exclude: DEFAULT_EXCLUDES
+                .iter()
+                .map(|glob| FilterRule::Path(glob.to_string()))
+                .collect(),
+            frame_rules: false,
+        }
+    }
+}
+
+impl FilterRules {
+    fn path_traced(&self, path: &str) -> bool {
+        self.include.iter().any(|rule| rule.matches_path(path))
+            || !self.exclude.iter().any(|rule| rule.matches_path(path))
+    }
+
+    fn frame_traced(&self, name: &str) -> bool {
+        self.include.iter().any(|rule| rule.matches_frame(name))
+            || !self.exclude.iter().any(|rule| rule.matches_frame(name))
+    }
+}
+
+/// Match `text` against `pattern`, where `*` matches any run of characters
+/// within one path segment, `**` any run including `/` and `?` a single
+/// character other than `/`.
+fn glob_match(pattern: &str, text: &str) -> bool {
+    if let Some(rest) = pattern.strip_prefix("**") {
+        return text
+            .char_indices()
+            .map(|(i, _)| i)
+            .chain([text.len()])
+            .any(|i| glob_match(rest, &text[i..]));
     }
-    PATTERNS.iter().any(|p| path.contains(p))
+    if let Some(rest) = pattern.strip_prefix('*') {
+        for (i, c) in text.char_indices() {
+            if glob_match(rest, &text[i..]) {
+                return true;
+            }
+            if c == '/' {
+                return false;
+            }
+        }
+        return glob_match(rest, "");
+    }
+    let mut pattern_chars = pattern.chars();
+    let mut text_chars = text.chars();
+    match (pattern_chars.next(), text_chars.next()) {
+        (None, None) => true,
+        (Some(p), Some(t)) if p == t || (p == '?' && t != '/') => {
+            glob_match(pattern_chars.as_str(), text_chars.as_str())
+        }
+        _ => false,
+    }
+}
+
+/// Path globs starting with `/` match the whole path; others match the
+/// path or any tail of it that starts after a `/` (`vendor/**` matches
+/// `/app/vendor/x.rb`).
+fn path_glob_match(pattern: &str, path: &str) -> bool {
+    glob_match(pattern, path)
+        || (!pattern.starts_with('/')
+            && path
+                .match_indices('/')
+                .any(|(i, _)| glob_match(pattern, &path[i + 1..])))
+}
+
+/// The name of the installed gem `path` belongs to: the directory after
+/// the last `/gems/` segment without its `-<version>` suffix.
+fn gem_name(path: &str) -> Option<&str> {
+    let start = path.rfind("/gems/")? + "/gems/".len();
+    let (dir, _) = path[start..].split_once('/')?;
+    let end = dir
+        .rmatch_indices('-')
+        .find(|(i, _)| dir[i + 1..].starts_with(|c: char| c.is_ascii_digit()))
+        .map_or(dir.len(), |(i, _)| i);
+    Some(&dir[..end])
+}
+
+/// Most path Strings `paths` holds; past it the cache starts over, so a
+/// program that keeps loading new files cannot grow it without bound.
+const MAX_CACHED_PATHS: usize = 4096;
+
+/// Whether `path` names a source file worth caching.  `eval`,
+/// `instance_eval(str)` and the like report a fresh `(eval at f.rb:3)`
+/// String on every call, which would only fill the cache.
+fn cacheable_path(path: &str) -> bool {
+    !path.starts_with('(')
+}
+
+/// Event path Strings, by address, and what `source_path` made of them.
+/// Holds at most `MAX_CACHED_PATHS` entries and starts over when full.
+#[derive(Default)]
+struct PathCache {
+    entries: HashMap<VALUE, Option<Rc<str>>>,
+}
+
+impl PathCache {
+    fn get(&self, path_val: VALUE) -> Option<&Option<Rc<str>>> {
+        self.entries.get(&path_val)
+    }
+
+    /// Remember `entry` for `path_val`.  Returns true when the cache was
+    /// emptied first to make room.
+    fn insert(&mut self, path_val: VALUE, entry: Option<Rc<str>>) -> bool {
+        let full = self.entries.len() >= MAX_CACHED_PATHS;
+        if full {
+            self.entries.clear();
+        }
+        self.entries.insert(path_val, entry);
+        full
+    }
+}
+
+/// The text of the event path `path_val`, or `None` for an ignored file.
+/// Converted and filtered once per source file path String; later events
+/// of the same file only do a hash lookup.
+unsafe fn source_path(recorder: &mut RecorderData, path_val: VALUE) -> Option<Rc<str>> {
+    if let Some(cached) = recorder.paths.get(path_val) {
+        return cached.clone();
+    }
+    let path = rstring_checked_or_empty(path_val);
+    let cacheable = cacheable_path(&path);
+    let entry = if recorder.options.filter.path_traced(&path) {
+        Some(Rc::from(path))
+    } else {
+        None
+    };
+    if cacheable {
+        if recorder.paths.insert(path_val, entry.clone()) {
+            rb_ary_clear(recorder.path_values);
+        }
+        rb_ary_push(recorder.path_values, path_val);
+    }
+    entry
 }
 
 // Legacy tree-based helpers (value_type_id, struct_value, to_value) have been
@@ -229,10 +817,48 @@ unsafe extern "C" fn recorder_free(ptr: *mut c_void) {
     }
 }
 
+/// Pin the objects whose addresses the recorder keeps: every object it
+/// stores as a raw `VALUE`, the path Strings `paths` is keyed on and the
+/// exceptions `thread_frames` follows.
+/// Hidden ivars and constants alone would let compaction move them.
+unsafe extern "C" fn recorder_mark(ptr: *mut c_void) {
+    let data = &(*(ptr as *mut Recorder)).data;
+    for val in [
+        data.traced_threads,
+        data.path_values,
+        data.serializers,
+        data.serializer_cache,
+        data.set_class,
+        data.big_decimal_class,
+        data.data_class,
+        data.open_struct_class,
+        data.date_class,
+        data.date_time_class,
+        data.time_with_zone_class,
+        data.pathname_class,
+        data.uri_class,
+        data.ip_addr_class,
+    ] {
+        rb_gc_mark(val);
+    }
+    if RB_TYPE_P(data.path_values, rb_sys::ruby_value_type::RUBY_T_ARRAY) {
+        let len = RARRAY_LEN(data.path_values) as usize;
+        let ptr = RARRAY_CONST_PTR(data.path_values);
+        for i in 0..len {
+            rb_gc_mark(*ptr.add(i));
+        }
+    }
+    for frames in data.thread_frames.values() {
+        if let Some(unwinding) = &frames.unwinding {
+            rb_gc_mark(unwinding.exception);
+        }
+    }
+}
+
 static mut RECORDER_TYPE: rb_data_type_t = rb_data_type_t {
     wrap_struct_name: c"Recorder".as_ptr() as *const c_char,
     function: rb_data_type_struct__bindgen_ty_1 {
-        dmark: None,
+        dmark: Some(recorder_mark),
         dfree: Some(recorder_free),
         dsize: None,
         dcompact: None,
@@ -256,6 +882,8 @@ unsafe fn get_recorder(obj: VALUE) -> *mut Recorder {
 }
 
 unsafe extern "C" fn ruby_recorder_alloc(klass: VALUE) -> VALUE {
+    let mut threads = ThreadNumbers::default();
+    threads.number(rb_thread_main());
     let recorder = Box::new(Recorder {
         tracer: Mutex::new(create_trace_writer(
             "ruby",
@@ -264,12 +892,37 @@ unsafe extern "C" fn ruby_recorder_alloc(klass: VALUE) -> VALUE {
         )),
         data: RecorderData {
             active: false,
+            global_hook: false,
+            traced_threads: Qnil.into(),
+            top_level_open: false,
             in_event_hook: false,
             thread_event_hook_installed: false,
             last_thread_id: None,
+            thread_names: HashMap::new(),
+            open_frames: HashMap::new(),
+            frame_filter: HashMap::new(),
+            frame_locals: HashMap::new(),
+            thread_frames: HashMap::new(),
+            options: RecorderOptions::default(),
             id: InternedSymbols::new(),
             set_class: Qnil.into(),
+            big_decimal_class: Qnil.into(),
+            data_class: Qnil.into(),
+            struct_types: HashMap::new(),
+            encoding_path: Vec::new(),
+            encoded_objects: HashSet::new(),
+            open_compounds: 0,
             open_struct_class: Qnil.into(),
+            date_class: Qnil.into(),
+            date_time_class: Qnil.into(),
+            time_with_zone_class: Qnil.into(),
+            pathname_class: Qnil.into(),
+            uri_class: Qnil.into(),
+            ip_addr_class: Qnil.into(),
+            paths: PathCache::default(),
+            path_values: Qnil.into(),
+            serializers: Qnil.into(),
+            serializer_cache: Qnil.into(),
             int_type_id: TypeId::default(),
             float_type_id: TypeId::default(),
             bool_type_id: TypeId::default(),
@@ -278,64 +931,230 @@ unsafe extern "C" fn ruby_recorder_alloc(klass: VALUE) -> VALUE {
             error_type_id: TypeId::default(),
         },
         out_dir: String::new(),
+        threads: Mutex::new(threads),
+        thread_events: Mutex::new(Vec::new()),
         streaming_encoder: StreamingValueEncoder::new(),
     });
     let ty = std::ptr::addr_of!(RECORDER_TYPE) as *const rb_data_type_t;
     rb_data_typed_object_wrap(klass, Box::into_raw(recorder) as *mut c_void, ty)
 }
 
+/// `event_hook_raw` as the hook function the VM takes.
+unsafe fn event_hook_func() -> rb_event_hook_func_t {
+    let raw_cb: unsafe extern "C" fn(VALUE, *mut rb_trace_arg_t) = event_hook_raw;
+    Some(transmute(raw_cb))
+}
+
+/// Prepare a hook installation: register the thread event callback once
+/// and reopen `<top-level>` when an earlier stop closed it, so recording
+/// several `record { }` scopes gives every scope an enclosing call.
+unsafe fn prepare_tracing(recorder: &mut Recorder) {
+    if !recorder.data.thread_event_hook_installed {
+        thread_register_callback(recorder);
+        recorder.data.thread_event_hook_installed = true;
+    }
+    if !recorder.data.top_level_open {
+        let mut locked_tracer = recorder.tracer.lock().unwrap();
+        let func_id = TraceWriter::ensure_function_id(
+            &mut **locked_tracer,
+            "<top-level>",
+            Path::new(""),
+            Line(1),
+        );
+        TraceWriter::register_call(&mut **locked_tracer, func_id, vec![]);
+        recorder.data.top_level_open = true;
+    }
+}
+
+/// Record every thread through a process-wide hook.  Hooks of threads
+/// traced with `enable_tracing_on_thread` are detached first, as they
+/// would see every event of their thread a second time; the threads stay
+/// listed so `disable_global_tracing` can reattach them.
 unsafe extern "C" fn enable_tracing(self_val: VALUE) -> VALUE {
     let recorder = &mut *get_recorder(self_val);
-    if !recorder.data.active {
-        if !recorder.data.thread_event_hook_installed {
-            thread_register_callback(recorder);
-            recorder.data.thread_event_hook_installed = true;
-        }
-
-        let raw_cb: unsafe extern "C" fn(VALUE, *mut rb_trace_arg_t) = event_hook_raw;
-        let func: rb_event_hook_func_t = Some(transmute(raw_cb));
+    if !recorder.data.global_hook {
+        remove_thread_hooks(recorder, self_val);
+        prepare_tracing(recorder);
         rb_add_event_hook2(
-            func,
-            RUBY_EVENT_LINE | RUBY_EVENT_CALL | RUBY_EVENT_RETURN | RUBY_EVENT_RAISE,
+            event_hook_func(),
+            recorder.data.options.event_mask(),
             self_val,
             rb_event_hook_flag_t::RUBY_EVENT_HOOK_FLAG_RAW_ARG,
         );
+        recorder.data.global_hook = true;
         recorder.data.active = true;
     }
     Qnil.into()
 }
 
+/// Record `thread` through a hook attached to it alone, leaving other
+/// threads untraced.  While every thread is recorded the thread is only
+/// listed; its hook is attached once the process-wide hook goes away.
+unsafe extern "C" fn enable_tracing_on_thread(self_val: VALUE, thread: VALUE) -> VALUE {
+    if rb_obj_is_kind_of(thread, rb_cThread) == 0 {
+        rb_raise(
+            rb_eTypeError,
+            c"expected a Thread".as_ptr() as *const c_char,
+        );
+    }
+    let recorder = &mut *get_recorder(self_val);
+    if TEST(rb_ary_includes(recorder.data.traced_threads, thread)) {
+        return Qnil.into();
+    }
+    if !recorder.data.global_hook {
+        prepare_tracing(recorder);
+        add_thread_hook(recorder, self_val, thread);
+    }
+    rb_ary_push(recorder.data.traced_threads, thread);
+    recorder.data.active = true;
+    Qnil.into()
+}
+
+/// Stop recording `thread`; recording ends (see `finish_tracing`) once no
+/// thread is left.
+unsafe extern "C" fn disable_tracing_on_thread(self_val: VALUE, thread: VALUE) -> VALUE {
+    let recorder = &mut *get_recorder(self_val);
+    if !TEST(rb_ary_includes(recorder.data.traced_threads, thread)) {
+        return Qnil.into();
+    }
+    rb_ary_delete(recorder.data.traced_threads, thread);
+    if recorder.data.global_hook {
+        return Qnil.into();
+    }
+    rb_thread_remove_event_hook_with_data(thread, event_hook_func(), self_val);
+    if RARRAY_LEN(recorder.data.traced_threads) == 0 {
+        finish_tracing(recorder);
+    }
+    Qnil.into()
+}
+
+/// Remove the process-wide hook from `enable_tracing` and go back to
+/// recording the threads listed by `enable_tracing_on_thread`; recording
+/// ends once none is listed.
+unsafe extern "C" fn disable_global_tracing(self_val: VALUE) -> VALUE {
+    let recorder = &mut *get_recorder(self_val);
+    if !recorder.data.global_hook {
+        return Qnil.into();
+    }
+    rb_remove_event_hook_with_data(event_hook_func(), self_val);
+    recorder.data.global_hook = false;
+    let threads = recorder.data.traced_threads;
+    let len = RARRAY_LEN(threads) as usize;
+    if len == 0 {
+        finish_tracing(recorder);
+        return Qnil.into();
+    }
+    let ptr = RARRAY_CONST_PTR(threads);
+    for i in 0..len {
+        add_thread_hook(recorder, self_val, *ptr.add(i));
+    }
+    Qnil.into()
+}
+
 unsafe extern "C" fn disable_tracing(self_val: VALUE) -> VALUE {
     let recorder = &mut *get_recorder(self_val);
     if recorder.data.active {
-        let raw_cb: unsafe extern "C" fn(VALUE, *mut rb_trace_arg_t) = event_hook_raw;
-        let func: rb_event_hook_func_t = Some(transmute(raw_cb));
-        rb_remove_event_hook_with_data(func, self_val);
-        recorder.data.active = false;
-
-        // Close the implicit top-level call opened in `initialize`.
-        //
-        // The Nim multi-stream call writer pairs `register_call` with
-        // `register_return`: the call record is only persisted when its
-        // matching return arrives (it stores the entry/exit step range
-        // computed from the step counter at call/return time).  Without
-        // this closing return, the `<top-level>` call record is never
-        // written, leaving steps that occur before the first user call
-        // (e.g. class definition steps in rb_sudoku_solver) with no
-        // enclosing call entry.  The downstream db-backend's
-        // `call_key_for_step` then returns CallKey(-1) for those steps
-        // and the calltrace pane renders nothing.
-        let mut locked_tracer = recorder.tracer.lock().unwrap();
-        TraceWriter::register_return(
-            &mut **locked_tracer,
-            ValueRecord::None {
-                type_id: recorder.data.error_type_id,
-            },
-        );
+        if recorder.data.global_hook {
+            rb_remove_event_hook_with_data(event_hook_func(), self_val);
+            recorder.data.global_hook = false;
+        } else {
+            remove_thread_hooks(recorder, self_val);
+        }
+        rb_ary_clear(recorder.data.traced_threads);
+        finish_tracing(recorder);
     }
     Qnil.into()
 }
 
+/// Attach the recorder's hook to `thread` alone.
+unsafe fn add_thread_hook(recorder: &Recorder, self_val: VALUE, thread: VALUE) {
+    rb_thread_add_event_hook2(
+        thread,
+        event_hook_func(),
+        recorder.data.options.event_mask(),
+        self_val,
+        rb_event_hook_flag_t::RUBY_EVENT_HOOK_FLAG_RAW_ARG,
+    );
+}
+
+/// Detach the hooks `enable_tracing_on_thread` attached, keeping the
+/// threads listed.
+unsafe fn remove_thread_hooks(recorder: &Recorder, self_val: VALUE) {
+    let threads = recorder.data.traced_threads;
+    let len = RARRAY_LEN(threads) as usize;
+    let ptr = RARRAY_CONST_PTR(threads);
+    for i in 0..len {
+        rb_thread_remove_event_hook_with_data(*ptr.add(i), event_hook_func(), self_val);
+    }
+}
+
+/// Whether events raised on the current thread are being recorded.
+unsafe fn recording_current_thread(recorder: &RecorderData) -> bool {
+    recorder.active
+        && (recorder.global_hook
+            || TEST(rb_ary_includes(
+                recorder.traced_threads,
+                rb_thread_current(),
+            )))
+}
+
+/// Mark the recorder inactive once its last hook is gone.
+unsafe fn finish_tracing(recorder: &mut Recorder) {
+    recorder.data.active = false;
+
+    // Close the implicit top-level call opened in `initialize`.
+    //
+    // The Nim multi-stream call writer pairs `register_call` with
+    // `register_return`: the call record is only persisted when its
+    // matching return arrives (it stores the entry/exit step range
+    // computed from the step counter at call/return time).  Without
+    // this closing return, the `<top-level>` call record is never
+    // written, leaving steps that occur before the first user call
+    // (e.g. class definition steps in rb_sudoku_solver) with no
+    // enclosing call entry.  The downstream db-backend's
+    // `call_key_for_step` then returns CallKey(-1) for those steps
+    // and the calltrace pane renders nothing.
+    //
+    // Frames still open (tracing stopped from inside a method, or an
+    // exception escaped the traced code) are closed first, each on the
+    // thread that opened it, so this return really lands on
+    // `<top-level>`.  The thread of the last event goes last, leaving the
+    // writer on it as before.
+    let mut locked_tracer = recorder.tracer.lock().unwrap();
+    write_thread_events(
+        &recorder.thread_events,
+        &mut recorder.data,
+        &mut **locked_tracer,
+    );
+    let error_type_id = recorder.data.error_type_id;
+    let none = || ValueRecord::None {
+        type_id: error_type_id,
+    };
+    let last_thread_id = recorder.data.last_thread_id;
+    let mut open_frames: Vec<(u64, usize)> = recorder.data.open_frames.drain().collect();
+    open_frames.sort_by_key(|&(thread_id, _)| (Some(thread_id) == last_thread_id, thread_id));
+    let mut writer_thread = last_thread_id;
+    for (thread_id, frames) in open_frames {
+        if writer_thread != Some(thread_id) {
+            TraceWriter::register_thread_switch(&mut **locked_tracer, thread_id);
+            writer_thread = Some(thread_id);
+        }
+        for _ in 0..frames {
+            TraceWriter::register_return(&mut **locked_tracer, none());
+        }
+    }
+    if let Some(thread_id) = last_thread_id.filter(|&id| writer_thread != Some(id)) {
+        TraceWriter::register_thread_switch(&mut **locked_tracer, thread_id);
+    }
+    if recorder.data.top_level_open {
+        TraceWriter::register_return(&mut **locked_tracer, none());
+    }
+    recorder.data.top_level_open = false;
+    recorder.data.frame_filter.clear();
+    recorder.data.frame_locals.clear();
+    recorder.data.thread_frames.clear();
+}
+
 // Hard-pinned to the canonical CTFS multi-stream output per
 // `codetracer-specs/Recorder-CLI-Conventions.md` §4 (CTFS-only).  The
 // recorder no longer accepts a format parameter: the JSON / Binary /
@@ -369,13 +1188,68 @@ unsafe fn cstr_to_string(ptr: *const c_char) -> Option<String> {
     CStr::from_ptr(ptr).to_str().ok().map(|s| s.to_string())
 }
 
+/// Name of a Ruby encoding, e.g. `Shift_JIS`.
+unsafe fn encoding_name(enc: *mut rb_encoding) -> String {
+    if enc.is_null() {
+        return String::default();
+    }
+    cstr_to_string((*enc).name as *const c_char).unwrap_or_default()
+}
+
+/// `rb_enc_str_coderange` result for a String with bytes that are not
+/// valid in its encoding.
+const ENC_CODERANGE_BROKEN: c_int = rb_sys::ruby_coderange_type::RUBY_ENC_CODERANGE_BROKEN as c_int;
+
+/// `val` as a UTF-8 String: itself when it is UTF-8 or US-ASCII, else
+/// transcoded with `rb_str_conv_enc`.  `None` for binary strings and for
+/// strings whose bytes are not valid in their encoding (or that have no
+/// UTF-8 converter), whose text cannot be recovered.
+unsafe fn utf8_string(val: VALUE) -> Option<VALUE> {
+    let enc = rb_enc_get(val);
+    let utf8 = rb_utf8_encoding();
+    if enc == utf8 || enc == rb_usascii_encoding() {
+        if rb_enc_str_coderange(val) == ENC_CODERANGE_BROKEN {
+            return None;
+        }
+        return Some(val);
+    }
+    if enc.is_null() || enc == rb_ascii8bit_encoding() {
+        return None;
+    }
+    // `rb_str_conv_enc` returns its argument unchanged when it cannot
+    // convert.  An empty string converts trivially.
+    let converted = rb_str_conv_enc(val, enc, utf8);
+    if converted != val || RSTRING_LEN(val) == 0 {
+        Some(converted)
+    } else {
+        None
+    }
+}
+
+/// The text of a String as UTF-8, transcoded from its own encoding where
+/// possible; bytes that are still invalid become replacement characters.
 unsafe fn rstring_lossy(val: VALUE) -> String {
+    let val = utf8_string(val).unwrap_or(val);
     let ptr = RSTRING_PTR(val);
     let len = RSTRING_LEN(val) as usize;
     let slice = std::slice::from_raw_parts(ptr as *const u8, len);
     String::from_utf8_lossy(slice).to_string()
 }
 
+/// At most the first `max_bytes` bytes of a String, cut back to a UTF-8
+/// character boundary so the prefix does not end in a replacement char.
+unsafe fn rstring_prefix_lossy(val: VALUE, max_bytes: usize) -> String {
+    let ptr = RSTRING_PTR(val);
+    let len = (RSTRING_LEN(val) as usize).min(max_bytes);
+    let slice = std::slice::from_raw_parts(ptr as *const u8, len);
+    let end = match std::str::from_utf8(slice) {
+        Ok(_) => len,
+        Err(e) if e.error_len().is_none() => e.valid_up_to(),
+        Err(_) => len,
+    };
+    String::from_utf8_lossy(&slice[..end]).to_string()
+}
+
 unsafe fn rstring_checked(val: VALUE) -> Result<String, FromUtf8Error> {
     let ptr = RSTRING_PTR(val);
     let len = RSTRING_LEN(val) as usize;
@@ -396,9 +1270,62 @@ unsafe extern "C" fn call_to_s(arg: VALUE) -> VALUE {
     rb_funcall(data.0, data.1, 0)
 }
 
+/// `val.<id>` with no arguments, or `None` (and the error discarded) if it
+/// raises.
+unsafe fn funcall_exception_safe(val: VALUE, id: ID) -> Option<VALUE> {
+    let mut state: c_int = 0;
+    let data = (val, id);
+    let result = rb_protect(Some(call_to_s), &data as *const _ as VALUE, &mut state);
+    if state != 0 {
+        rb_set_errinfo(Qnil.into());
+        None
+    } else {
+        Some(result)
+    }
+}
+
+unsafe extern "C" fn call_with_args(arg: VALUE) -> VALUE {
+    let data = &*(arg as *const (VALUE, ID, &[VALUE]));
+    rb_funcallv(data.0, data.1, data.2.len() as c_int, data.2.as_ptr())
+}
+
+/// `val.<id>(*args)`, or `None` (and the error discarded) if it raises.
+unsafe fn funcall_args_exception_safe(val: VALUE, id: ID, args: &[VALUE]) -> Option<VALUE> {
+    let mut state: c_int = 0;
+    let data = (val, id, args);
+    let result = rb_protect(Some(call_with_args), &data as *const _ as VALUE, &mut state);
+    if state != 0 {
+        rb_set_errinfo(Qnil.into());
+        None
+    } else {
+        Some(result)
+    }
+}
+
+/// `val` as an `i64` when it is a Fixnum; `None` for anything else.
+unsafe fn fixnum_value(val: VALUE) -> Option<i64> {
+    if FIXNUM_P(val) {
+        Some(rb_num2long(val) as i64)
+    } else {
+        None
+    }
+}
+
+/// `val` when it is a String.
+unsafe fn string_value(val: VALUE) -> Option<VALUE> {
+    if RB_TYPE_P(val, rb_sys::ruby_value_type::RUBY_T_STRING) {
+        Some(val)
+    } else {
+        None
+    }
+}
+
 unsafe fn value_to_string_exception_safe(recorder: &RecorderData, val: VALUE) -> String {
     if RB_TYPE_P(val, rb_sys::ruby_value_type::RUBY_T_STRING) {
         rstring_lossy(val)
+    } else if recorder.options.pure_capture {
+        // `Kernel#to_s` without dispatch: `#<Class:0x...>`.
+        rstring_lossy(rb_any_to_s(val))
     } else {
         let mut state: c_int = 0;
         let data = (val, recorder.id.to_s);
@@ -412,10 +1339,21 @@ unsafe fn value_to_string_exception_safe(recorder: &RecorderData, val: VALUE) ->
     }
 }
 
-/// Maximum recursion depth for streaming encoding. Prevents stack overflow
-/// from deeply nested Ruby structures and stays within the encoder's
-/// compound nesting limit (32 levels).
-const MAX_STREAMING_DEPTH: usize = 10;
+/// The default `max_depth`.  One level can open several compounds, so the
+/// encoder's nesting limit is enforced separately (`MAX_COMPOUND_NESTING`).
+const DEFAULT_MAX_DEPTH: usize = 10;
+
+/// The encoder's compound nesting limit, and the largest `max_depth`
+/// accepted: every expanded level opens at least one compound, so deeper
+/// limits could never be reached.
+const MAX_COMPOUND_NESTING: usize = 32;
+
+/// Compounds one heap object may open around its elements (`<object>`
+/// tag, `<truncated>`, the container and a Hash entry pair), plus the two
+/// an immediate or String element may add (`<truncated>` and a `Bytes`
+/// sequence, or a Complex of Rationals).  An object is only expanded when
+/// this many levels are still free.
+const LEVEL_COMPOUNDS: usize = 6;
 
 /// Encode a Ruby `VALUE` directly to CBOR bytes using the streaming encoder,
 /// bypassing intermediate `ValueRecord` tree allocation.
@@ -430,10 +1368,6 @@ unsafe fn encode_ruby_value_streaming(
     val: VALUE,
     depth: usize,
 ) {
-    if depth == 0 {
-        encoder.write_none(recorder.error_type_id);
-        return;
-    }
     if NIL_P(val) {
         encoder.write_none(recorder.error_type_id);
         return;
@@ -443,8 +1377,19 @@ unsafe fn encode_ruby_value_streaming(
         return;
     }
     if RB_INTEGER_TYPE_P(val) {
-        let i = rb_num2long(val) as i64;
-        encoder.write_int(i, recorder.int_type_id);
+        if FIXNUM_P(val) {
+            encoder.write_int(rb_num2long(val) as i64, recorder.int_type_id);
+            return;
+        }
+        // Bignums would raise `RangeError` from `rb_num2long` inside the
+        // event hook.  Those that still fit in an i64 stay Int; larger
+        // ones are written losslessly as their decimal digits, typed
+        // `Integer`.
+        let digits = rstring_lossy(rb_big2str(val, 10));
+        match digits.parse::<i64>() {
+            Ok(i) => encoder.write_int(i, recorder.int_type_id),
+            Err(_) => encoder.write_raw(&digits, recorder.int_type_id),
+        }
         return;
     }
     if RB_FLOAT_TYPE_P(val) {
@@ -459,53 +1404,367 @@ unsafe fn encode_ruby_value_streaming(
         encoder.write_float(f, type_id);
         return;
     }
+    if RB_TYPE_P(val, rb_sys::ruby_value_type::RUBY_T_RATIONAL) {
+        let type_id = TraceWriter::ensure_type_id(tracer, TypeKind::Tuple, "Rational");
+        encoder.begin_tuple(type_id, 2);
+        encode_ruby_value_streaming(
+            recorder,
+            tracer,
+            encoder,
+            rb_rational_num(val),
+            depth.saturating_sub(1),
+        );
+        encode_ruby_value_streaming(
+            recorder,
+            tracer,
+            encoder,
+            rb_rational_den(val),
+            depth.saturating_sub(1),
+        );
+        encoder.end_compound();
+        return;
+    }
+    if RB_TYPE_P(val, rb_sys::ruby_value_type::RUBY_T_COMPLEX) {
+        let type_id = TraceWriter::ensure_type_id(tracer, TypeKind::Tuple, "Complex");
+        encoder.begin_tuple(type_id, 2);
+        encode_ruby_value_streaming(
+            recorder,
+            tracer,
+            encoder,
+            rb_complex_real(val),
+            depth.saturating_sub(1),
+        );
+        encode_ruby_value_streaming(
+            recorder,
+            tracer,
+            encoder,
+            rb_complex_imag(val),
+            depth.saturating_sub(1),
+        );
+        encoder.end_compound();
+        return;
+    }
     if RB_SYMBOL_P(val) {
         let text = cstr_to_string(rb_id2name(rb_sym2id(val))).unwrap_or_default();
         encoder.write_string(&text, recorder.symbol_type_id);
         return;
     }
     if RB_TYPE_P(val, rb_sys::ruby_value_type::RUBY_T_STRING) {
-        let text = rstring_lossy(val);
-        encoder.write_string(&text, recorder.string_type_id);
+        encode_string_streaming(recorder, tracer, encoder, val);
         return;
     }
-    if RB_TYPE_P(val, rb_sys::ruby_value_type::RUBY_T_ARRAY) {
-        let len = RARRAY_LEN(val) as usize;
-        let type_id = TraceWriter::ensure_type_id(tracer, TypeKind::Seq, "Array");
-        encoder.begin_sequence(type_id, len);
-        let ptr = RARRAY_CONST_PTR(val);
-        for i in 0..len {
-            let elem = *ptr.add(i);
-            encode_ruby_value_streaming(recorder, tracer, encoder, elem, depth - 1);
-        }
-        encoder.end_compound();
+    // Everything past this point is a heap object that may (directly or
+    // through its elements) refer back to itself.  An object already on
+    // the path from the root is written as a back-reference, so cycles end
+    // at the first repetition instead of at `max_depth`; it
+    // holds the target's position on that path (0 for the root, counting
+    // objects only, not Hash entry pairs or `<truncated>` markers).  With
+    // `object_ids`, every object is tagged with its `object_id`, any
+    // repeat within the value becomes a back-reference, exposing
+    // aliasing, and back-references hold the `object_id` instead.
+    let object_ids = recorder.options.object_ids;
+    let path_index = recorder.encoding_path.iter().position(|&v| v == val);
+    if path_index.is_some() || (object_ids && recorder.encoded_objects.contains(&val)) {
+        let type_id = TraceWriter::ensure_type_id(tracer, TypeKind::Recursion, "<back-reference>");
+        let target = match path_index {
+            Some(index) if !object_ids => index as i64,
+            _ => rb_num2long(rb_obj_id(val)) as i64,
+        };
+        encoder.write_raw(&target.to_string(), type_id);
         return;
     }
-    if RB_TYPE_P(val, rb_sys::ruby_value_type::RUBY_T_HASH) {
-        let pairs = rb_funcall(val, recorder.id.to_a, 0);
-        let len = RARRAY_LEN(pairs) as usize;
-        let ptr = RARRAY_CONST_PTR(pairs);
-        let seq_type_id = TraceWriter::ensure_type_id(tracer, TypeKind::Seq, "Hash");
-        encoder.begin_sequence(seq_type_id, len);
-        for i in 0..len {
-            let pair = *ptr.add(i);
-            if !RB_TYPE_P(pair, rb_sys::ruby_value_type::RUBY_T_ARRAY) || RARRAY_LEN(pair) < 2 {
-                // Emit none for malformed pairs to preserve element count.
-                encoder.write_none(recorder.error_type_id);
-                continue;
+    if depth == 0 || recorder.open_compounds + LEVEL_COMPOUNDS > MAX_COMPOUND_NESTING {
+        write_depth_limit(tracer, encoder, val);
+        return;
+    }
+    recorder.encoding_path.push(val);
+    if object_ids {
+        recorder.encoded_objects.insert(val);
+        let type_id = TraceWriter::ensure_type_id(tracer, TypeKind::Ref, "<object>");
+        encoder.begin_tuple(type_id, 2);
+        encoder.write_int(rb_num2long(rb_obj_id(val)) as i64, recorder.int_type_id);
+        recorder.open_compounds += 1;
+    }
+    encode_heap_value_streaming(recorder, tracer, encoder, val, depth);
+    if object_ids {
+        recorder.open_compounds -= 1;
+        encoder.end_compound();
+    }
+    recorder.encoding_path.pop();
+}
+
+/// Write `val`, nested deeper than `max_depth` or the encoder allows, as
+/// its class name so the cut is visible instead of reading as `nil`.
+unsafe fn write_depth_limit(
+    tracer: &mut dyn TraceWriter,
+    encoder: &mut StreamingValueEncoder,
+    val: VALUE,
+) {
+    let class_name = cstr_to_string(rb_obj_classname(val)).unwrap_or_default();
+    let type_id = TraceWriter::ensure_type_id(tracer, TypeKind::NonExpanded, "<depth-limit>");
+    encoder.write_raw(&class_name, type_id);
+}
+
+/// Encode a String.  UTF-8 and US-ASCII strings are written as they are;
+/// other encodings are transcoded to UTF-8 and typed `String[<encoding>]`.
+/// Binary (ASCII-8BIT) strings, and strings with invalid bytes or that
+/// cannot be transcoded, are written as a sequence of byte values typed
+/// `Bytes[<encoding>]`.  `max_string_bytes` counts the bytes written: UTF-8
+/// bytes for text, source bytes for byte values.  A cut string is recorded
+/// with its original `bytesize`.
+unsafe fn encode_string_streaming(
+    recorder: &mut RecorderData,
+    tracer: &mut dyn TraceWriter,
+    encoder: &mut StreamingValueEncoder,
+    val: VALUE,
+) {
+    let enc = rb_enc_get(val);
+    let len = RSTRING_LEN(val) as usize;
+    let max_bytes = recorder.options.max_string_bytes;
+    // A string to transcode is first cut to `max_bytes` characters, which
+    // is at least `max_bytes` UTF-8 bytes, so only what can be written is
+    // converted.  Broken strings stay whole and are written as bytes.
+    let transcoded = !enc.is_null()
+        && enc != rb_utf8_encoding()
+        && enc != rb_usascii_encoding()
+        && enc != rb_ascii8bit_encoding();
+    let cut = transcoded
+        && len > max_bytes
+        && rb_enc_str_coderange(val) != ENC_CODERANGE_BROKEN
+        && rb_str_strlen(val) as usize > max_bytes;
+    let utf8 = if cut {
+        utf8_string(rb_str_substr(val, 0, max_bytes as std::os::raw::c_long))
+    } else {
+        utf8_string(val)
+    };
+    let truncated = cut || RSTRING_LEN(utf8.unwrap_or(val)) as usize > max_bytes;
+    if truncated {
+        begin_truncated(recorder, tracer, encoder, len);
+    }
+    match utf8 {
+        Some(utf8) => {
+            let type_id = if enc == rb_utf8_encoding() || enc == rb_usascii_encoding() {
+                recorder.string_type_id
+            } else {
+                let lang_type = format!("String[{}]", encoding_name(enc));
+                TraceWriter::ensure_type_id(tracer, TypeKind::String, &lang_type)
+            };
+            encoder.write_string(&rstring_prefix_lossy(utf8, max_bytes), type_id);
+        }
+        None => {
+            let lang_type = format!("Bytes[{}]", encoding_name(enc));
+            let type_id = TraceWriter::ensure_type_id(tracer, TypeKind::Seq, &lang_type);
+            let shown = len.min(max_bytes);
+            let bytes = std::slice::from_raw_parts(RSTRING_PTR(val) as *const u8, shown);
+            encoder.begin_sequence(type_id, shown);
+            for byte in bytes {
+                encoder.write_int(*byte as i64, recorder.int_type_id);
             }
-            let pair_ptr = RARRAY_CONST_PTR(pair);
-            let key = *pair_ptr.add(0);
-            let val_elem = *pair_ptr.add(1);
-            // Encode each pair as a 2-element tuple with fields "k" and "v",
-            // matching the struct_value("Pair", ...) encoding in the legacy path.
-            let pair_type_id = TraceWriter::ensure_type_id(tracer, TypeKind::Tuple, "Pair");
-            encoder.begin_tuple(pair_type_id, 2);
-            encode_ruby_value_streaming(recorder, tracer, encoder, key, depth - 1);
-            encode_ruby_value_streaming(recorder, tracer, encoder, val_elem, depth - 1);
             encoder.end_compound();
         }
+    }
+    if truncated {
         encoder.end_compound();
+    }
+}
+
+/// Bumped by `CodeTracerNativeRecorder.ancestry_changed`, which
+/// `CodeTracer::AncestryWatch` calls whenever a module is included,
+/// prepended or extended anywhere.
+static ANCESTRY_GENERATION: AtomicU64 = AtomicU64::new(0);
+
+/// The serializer registered for the class of `val` or its nearest
+/// ancestor, or `nil`.  The singleton class of `val`, when it has one, is
+/// searched first, so modules `val` was extended with count.  Resolutions
+/// are cached for named classes until the next `ANCESTRY_GENERATION`;
+/// anonymous and singleton classes are resolved every time, so the cache
+/// keeps no class alive that would otherwise be collected.
+/// `register_serializer` drops every cached resolution.
+unsafe fn serializer_for(recorder: &RecorderData, val: VALUE) -> VALUE {
+    if NIL_P(recorder.serializers) {
+        return Qnil.into();
+    }
+    let klass = (*(val as *const RBasic)).klass;
+    let cacheable = !singleton_class_p(klass) && !NIL_P(rb_mod_name(klass));
+    let generation = ANCESTRY_GENERATION.load(Ordering::Relaxed);
+    if cacheable {
+        let cached = rb_hash_lookup2(recorder.serializer_cache, klass, Qnil.into());
+        if !NIL_P(cached) && rb_num2long(rb_ary_entry(cached, 1)) as u64 == generation {
+            return rb_ary_entry(cached, 0);
+        }
+    }
+    let mut resolved: VALUE = Qnil.into();
+    let ancestors = rb_mod_ancestors(klass);
+    let ptr = RARRAY_CONST_PTR(ancestors);
+    for i in 0..RARRAY_LEN(ancestors) as usize {
+        // A `nil` entry opts a subclass out of its ancestor's serializer.
+        let entry = rb_hash_lookup2(recorder.serializers, *ptr.add(i), Qfalse.into());
+        if entry != (Qfalse as VALUE) {
+            resolved = entry;
+            break;
+        }
+    }
+    if cacheable {
+        let entry = rb_ary_new_capa(2);
+        rb_ary_push(entry, resolved);
+        rb_ary_push(entry, rb_int2inum(generation as isize));
+        rb_hash_aset(recorder.serializer_cache, klass, entry);
+    }
+    resolved
+}
+
+/// `CodeTracerNativeRecorder.ancestry_changed`: some class or object
+/// gained a module, so cached serializer resolutions may be stale.
+unsafe extern "C" fn ancestry_changed(_klass: VALUE) -> VALUE {
+    ANCESTRY_GENERATION.fetch_add(1, Ordering::Relaxed);
+    Qnil.into()
+}
+
+unsafe extern "C" fn call_serializer(arg: VALUE) -> VALUE {
+    let (serializer, val, call) = *(arg as *const (VALUE, VALUE, ID));
+    if RB_SYMBOL_P(serializer) {
+        rb_funcall(val, rb_sym2id(serializer), 0)
+    } else {
+        rb_funcall(serializer, call, 1, val)
+    }
+}
+
+/// Write the `path` and `line` fields of a Proc or Method from its
+/// `source_location`; both are `nil` for callables implemented in C and
+/// under `pure_capture`, which does not ask.
+unsafe fn write_source_location(
+    recorder: &RecorderData,
+    encoder: &mut StreamingValueEncoder,
+    callable: VALUE,
+) {
+    let location = if recorder.options.pure_capture {
+        None
+    } else {
+        funcall_exception_safe(callable, recorder.id.source_location)
+    }
+    .filter(|&location| {
+        RB_TYPE_P(location, rb_sys::ruby_value_type::RUBY_T_ARRAY) && RARRAY_LEN(location) >= 2
+    });
+    let line = location.and_then(|location| fixnum_value(*RARRAY_CONST_PTR(location).add(1)));
+    if let (Some(location), Some(line)) = (location, line) {
+        encoder.write_string(
+            &rstring_checked_or_empty(*RARRAY_CONST_PTR(location)),
+            recorder.string_type_id,
+        );
+        encoder.write_int(line, recorder.int_type_id);
+    } else {
+        encoder.write_none(recorder.error_type_id);
+        encoder.write_none(recorder.error_type_id);
+    }
+}
+
+/// Name of a class or module for display: its constant path, or the
+/// `#<Class:...>` form for anonymous and singleton classes.
+unsafe fn module_display_name(module: VALUE) -> String {
+    let name = rb_mod_name(module);
+    if NIL_P(name) {
+        rstring_lossy(rb_any_to_s(module))
+    } else {
+        rstring_lossy(name)
+    }
+}
+
+/// Encode a Class as `{name, superclass, ancestors}` and a Module as
+/// `{name, ancestors}`, naming the related modules rather than expanding
+/// them.  Only the first `max_elements` ancestors are written.  Everything
+/// is read with C APIs, so this is also `pure_capture` safe.
+unsafe fn encode_module_streaming(
+    recorder: &mut RecorderData,
+    tracer: &mut dyn TraceWriter,
+    encoder: &mut StreamingValueEncoder,
+    module: VALUE,
+) {
+    let is_class = RB_TYPE_P(module, rb_sys::ruby_value_type::RUBY_T_CLASS);
+    let (class_name, fields) = if is_class {
+        ("Class", vec!["name", "superclass", "ancestors"])
+    } else {
+        ("Module", vec!["name", "ancestors"])
+    };
+    let fields: Vec<String> = fields.into_iter().map(String::from).collect();
+    let type_id = ensure_struct_type_id(recorder, tracer, class_name, &fields);
+    encoder.begin_struct(type_id, fields.len());
+    encoder.write_string(&module_display_name(module), recorder.string_type_id);
+    if is_class {
+        let superclass = rb_class_superclass(module);
+        if NIL_P(superclass) {
+            encoder.write_none(recorder.error_type_id);
+        } else {
+            encoder.write_string(&module_display_name(superclass), recorder.string_type_id);
+        }
+    }
+    let ancestors = rb_mod_ancestors(module);
+    let len = RARRAY_LEN(ancestors) as usize;
+    let shown = len.min(recorder.options.max_elements);
+    if shown < len {
+        begin_truncated(recorder, tracer, encoder, len);
+    }
+    let ptr = RARRAY_CONST_PTR(ancestors);
+    let ancestors_type_id = TraceWriter::ensure_type_id(tracer, TypeKind::Seq, "Ancestors");
+    encoder.begin_sequence(ancestors_type_id, shown);
+    for i in 0..shown {
+        encoder.write_string(&module_display_name(*ptr.add(i)), recorder.string_type_id);
+    }
+    encoder.end_compound();
+    if shown < len {
+        encoder.end_compound();
+    }
+    encoder.end_compound();
+}
+
+/// Open the `(original length, partial value)` tuple that marks a String,
+/// Array, Hash or Set cut down to the capture limits; the caller encodes
+/// the partial value and closes the tuple.
+fn begin_truncated(
+    recorder: &RecorderData,
+    tracer: &mut dyn TraceWriter,
+    encoder: &mut StreamingValueEncoder,
+    original_len: usize,
+) {
+    let type_id = TraceWriter::ensure_type_id(tracer, TypeKind::Tuple, "<truncated>");
+    encoder.begin_tuple(type_id, 2);
+    encoder.write_int(original_len as i64, recorder.int_type_id);
+}
+
+/// The heap-object half of `encode_ruby_value_streaming`: containers,
+/// records and everything else that is not an immediate or a String.
+unsafe fn encode_heap_value_streaming(
+    recorder: &mut RecorderData,
+    tracer: &mut dyn TraceWriter,
+    encoder: &mut StreamingValueEncoder,
+    val: VALUE,
+    depth: usize,
+) {
+    if !recorder.options.pure_capture {
+        let serializer = serializer_for(recorder, val);
+        if !NIL_P(serializer) {
+            // Record whatever the serializer returns in place of `val`; if
+            // it raises, fall back to the built-in encoding.
+            let mut state: c_int = 0;
+            let data = (serializer, val, recorder.id.call);
+            let replacement = rb_protect(
+                Some(call_serializer),
+                &data as *const _ as VALUE,
+                &mut state,
+            );
+            if state == 0 {
+                encode_ruby_value_streaming(recorder, tracer, encoder, replacement, depth - 1);
+                return;
+            }
+            rb_set_errinfo(Qnil.into());
+        }
+    }
+    if RB_TYPE_P(val, rb_sys::ruby_value_type::RUBY_T_ARRAY) {
+        let type_id = TraceWriter::ensure_type_id(tracer, TypeKind::Seq, "Array");
+        encode_elements_streaming(recorder, tracer, encoder, val, type_id, depth);
+        return;
+    }
+    if RB_TYPE_P(val, rb_sys::ruby_value_type::RUBY_T_HASH) {
+        encode_hash_streaming(recorder, tracer, encoder, val, depth);
         return;
     }
     if rb_obj_is_kind_of(val, rb_cThread) != 0 {
@@ -514,232 +1773,1488 @@ unsafe fn encode_ruby_value_streaming(
         encoder.end_compound();
         return;
     }
+    if TEST(rb_obj_is_proc(val)) {
+        // Procs and lambdas: where they were defined (`source_location`)
+        // instead of `to_s`, which embeds the object's address.
+        let fields = ["path", "line", "lambda", "arity"].map(String::from);
+        let type_id = ensure_struct_type_id(recorder, tracer, "Proc", &fields);
+        encoder.begin_struct(type_id, fields.len());
+        write_source_location(recorder, encoder, val);
+        encoder.write_bool(TEST(rb_proc_lambda_p(val)), recorder.bool_type_id);
+        encoder.write_int(rb_proc_arity(val) as i64, recorder.int_type_id);
+        encoder.end_compound();
+        return;
+    }
+    if !recorder.options.pure_capture && TEST(rb_obj_is_method(val)) {
+        // `Method` / `UnboundMethod`; their details are only reachable
+        // through method calls, hence not under `pure_capture`.
+        let class_name =
+            cstr_to_string(rb_obj_classname(val)).unwrap_or_else(|| "Method".to_string());
+        let fields = ["name", "owner", "path", "line", "arity"].map(String::from);
+        let type_id = ensure_struct_type_id(recorder, tracer, &class_name, &fields);
+        // A broken override of any of these leaves its field empty.
+        let name = funcall_exception_safe(val, recorder.id.name).filter(|&name| RB_SYMBOL_P(name));
+        let owner = funcall_exception_safe(val, recorder.id.owner).filter(|&owner| {
+            RB_TYPE_P(owner, rb_sys::ruby_value_type::RUBY_T_CLASS)
+                || RB_TYPE_P(owner, rb_sys::ruby_value_type::RUBY_T_MODULE)
+        });
+        let arity =
+            funcall_exception_safe(val, recorder.id.arity).and_then(|arity| fixnum_value(arity));
+        encoder.begin_struct(type_id, fields.len());
+        let name = name
+            .and_then(|name| cstr_to_string(rb_id2name(rb_sym2id(name))))
+            .unwrap_or_default();
+        encoder.write_string(&name, recorder.symbol_type_id);
+        match owner {
+            Some(owner) => {
+                encoder.write_string(&module_display_name(owner), recorder.string_type_id)
+            }
+            None => encoder.write_none(recorder.error_type_id),
+        }
+        write_source_location(recorder, encoder, val);
+        match arity {
+            Some(arity) => encoder.write_int(arity, recorder.int_type_id),
+            None => encoder.write_none(recorder.error_type_id),
+        }
+        encoder.end_compound();
+        return;
+    }
+    if RB_TYPE_P(val, rb_sys::ruby_value_type::RUBY_T_CLASS)
+        || RB_TYPE_P(val, rb_sys::ruby_value_type::RUBY_T_MODULE)
+    {
+        encode_module_streaming(recorder, tracer, encoder, val);
+        return;
+    }
+    if rb_obj_is_kind_of(val, rb_eException) != 0 {
+        encode_exception_streaming(recorder, tracer, encoder, val, depth);
+        return;
+    }
+    if rb_obj_is_kind_of(val, rb_cIO) != 0 {
+        encode_io_streaming(recorder, tracer, encoder, val);
+        return;
+    }
+    if rb_obj_is_kind_of(val, rb_cEnumerator) != 0
+        && encode_enumerator_streaming(recorder, tracer, encoder, val, depth)
+    {
+        return;
+    }
     if rb_obj_is_kind_of(val, rb_cRange) != 0 {
-        let begin_val = rb_funcall(val, recorder.id.begin, 0);
-        let end_val = rb_funcall(val, recorder.id.end, 0);
+        let mut begin_val: VALUE = Qnil.into();
+        let mut end_val: VALUE = Qnil.into();
+        let mut exclude_end: c_int = 0;
+        rb_range_values(val, &mut begin_val, &mut end_val, &mut exclude_end);
         let type_id = TraceWriter::ensure_type_id(tracer, TypeKind::Tuple, "Range");
         encoder.begin_tuple(type_id, 2);
+        recorder.open_compounds += 1;
         encode_ruby_value_streaming(recorder, tracer, encoder, begin_val, depth - 1);
         encode_ruby_value_streaming(recorder, tracer, encoder, end_val, depth - 1);
+        recorder.open_compounds -= 1;
         encoder.end_compound();
         return;
     }
+    // Set, BigDecimal and OpenStruct keep their state behind Ruby methods;
+    // `pure_capture` encodes them as plain objects further down instead.
+    let pure = recorder.options.pure_capture;
     if NIL_P(recorder.set_class) {
-        if rb_const_defined(rb_cObject, recorder.id.set_const) != 0 {
-            recorder.set_class = rb_const_get(rb_cObject, recorder.id.set_const);
-        }
+        recorder.set_class = loaded_class_const(recorder, rb_cObject, recorder.id.set_const);
     }
-    if !NIL_P(recorder.set_class) && rb_obj_is_kind_of(val, recorder.set_class) != 0 {
-        let arr = rb_funcall(val, recorder.id.to_a, 0);
-        if RB_TYPE_P(arr, rb_sys::ruby_value_type::RUBY_T_ARRAY) {
-            let len = RARRAY_LEN(arr) as usize;
-            let ptr = RARRAY_CONST_PTR(arr);
+    if !pure && !NIL_P(recorder.set_class) && rb_obj_is_kind_of(val, recorder.set_class) != 0 {
+        // Only the elements kept under `max_elements` are copied out
+        // (`first(n)` stops iterating after them).
+        let len = funcall_exception_safe(val, recorder.id.size).and_then(|v| fixnum_value(v));
+        let max_elements = recorder.options.max_elements;
+        let arr = match len {
+            Some(len) if len as usize > max_elements => funcall_args_exception_safe(
+                val,
+                recorder.id.first,
+                &[rb_int2inum(max_elements as isize)],
+            ),
+            _ => funcall_exception_safe(val, recorder.id.to_a),
+        };
+        if let Some(arr) = arr.filter(|arr| RB_TYPE_P(*arr, rb_sys::ruby_value_type::RUBY_T_ARRAY))
+        {
             let type_id = TraceWriter::ensure_type_id(tracer, TypeKind::Seq, "Set");
-            encoder.begin_sequence(type_id, len);
-            for i in 0..len {
-                let elem = *ptr.add(i);
-                encode_ruby_value_streaming(recorder, tracer, encoder, elem, depth - 1);
-            }
+            let len = len.map_or(RARRAY_LEN(arr) as usize, |len| len as usize);
+            encode_leading_elements_streaming(recorder, tracer, encoder, arr, len, type_id, depth);
+            return;
+        }
+    }
+    if NIL_P(recorder.big_decimal_class) {
+        recorder.big_decimal_class =
+            loaded_class_const(recorder, rb_cObject, recorder.id.big_decimal_const);
+    }
+    if !pure
+        && !NIL_P(recorder.big_decimal_class)
+        && rb_obj_is_kind_of(val, recorder.big_decimal_class) != 0
+    {
+        // The exact value as plain decimal digits (`to_s("F")`, never
+        // rounded through Float) and its number of significant digits.
+        // Subclasses can override both, so a failing call falls back to
+        // the generic object encoding.
+        let format = rb_str_new_cstr(c"F".as_ptr() as *const c_char);
+        let digits = funcall_args_exception_safe(val, recorder.id.to_s, &[format])
+            .and_then(|v| string_value(v));
+        let precision = funcall_exception_safe(val, recorder.id.precision);
+        if let (Some(digits), Some(precision)) = (digits, precision) {
+            let type_id = TraceWriter::ensure_type_id(tracer, TypeKind::Tuple, "BigDecimal");
+            encoder.begin_tuple(type_id, 2);
+            encoder.write_string(&rstring_lossy(digits), recorder.string_type_id);
+            encode_ruby_value_streaming(recorder, tracer, encoder, precision, depth - 1);
             encoder.end_compound();
             return;
         }
     }
     if rb_obj_is_kind_of(val, rb_cTime) != 0 {
-        let sec = rb_funcall(val, recorder.id.to_i, 0);
-        let nsec = rb_funcall(val, recorder.id.nsec, 0);
-        let type_id = TraceWriter::ensure_type_id(tracer, TypeKind::Tuple, "Time");
-        encoder.begin_tuple(type_id, 2);
-        encode_ruby_value_streaming(recorder, tracer, encoder, sec, depth - 1);
-        encode_ruby_value_streaming(recorder, tracer, encoder, nsec, depth - 1);
-        encoder.end_compound();
+        let time = rb_time_timespec(val);
+        // The zone abbreviation is only reachable through `Time#zone`.
+        let zone = if pure {
+            Qnil.into()
+        } else {
+            funcall_exception_safe(val, recorder.id.zone).unwrap_or(Qnil.into())
+        };
+        encode_time_streaming(
+            recorder,
+            tracer,
+            encoder,
+            "Time",
+            (time.tv_sec as i64, time.tv_nsec as i64),
+            rb_time_utc_offset(val),
+            zone,
+        );
+        return;
+    }
+    if !pure && encode_stdlib_value_streaming(recorder, tracer, encoder, val) {
         return;
     }
-    if rb_obj_is_kind_of(val, rb_cRegexp) != 0 {
+    if !pure && rb_obj_is_kind_of(val, rb_cRegexp) != 0 {
         let src = rb_funcall(val, recorder.id.source, 0);
-        let opts = rb_funcall(val, recorder.id.options, 0);
         let type_id = TraceWriter::ensure_type_id(tracer, TypeKind::Tuple, "Regexp");
         encoder.begin_tuple(type_id, 2);
         encode_ruby_value_streaming(recorder, tracer, encoder, src, depth - 1);
-        encode_ruby_value_streaming(recorder, tracer, encoder, opts, depth - 1);
+        encoder.write_int(rb_reg_options(val) as i64, recorder.int_type_id);
         encoder.end_compound();
         return;
     }
-    if rb_obj_is_kind_of(val, rb_cStruct) != 0 {
+    if NIL_P(recorder.data_class) {
+        // `Data` (Ruby 3.2+); older Rubies have no such constant.
+        recorder.data_class = loaded_class_const(recorder, rb_cObject, recorder.id.data_const);
+    }
+    if rb_obj_is_kind_of(val, rb_cStruct) != 0
+        || (!NIL_P(recorder.data_class) && rb_obj_is_kind_of(val, recorder.data_class) != 0)
+    {
+        // Members and values are read from the struct itself, so
+        // overridden accessors (or `Data`'s lack of `values`) don't matter.
         let class_name =
             cstr_to_string(rb_obj_classname(val)).unwrap_or_else(|| "Struct".to_string());
-        let members = rb_funcall(val, recorder.id.members, 0);
-        let values = rb_funcall(val, recorder.id.values, 0);
-        if !RB_TYPE_P(members, rb_sys::ruby_value_type::RUBY_T_ARRAY)
-            || !RB_TYPE_P(values, rb_sys::ruby_value_type::RUBY_T_ARRAY)
-        {
-            let text = value_to_string_exception_safe(recorder, val);
-            let type_id = TraceWriter::ensure_type_id(tracer, TypeKind::Raw, &class_name);
-            encoder.write_raw(&text, type_id);
-            return;
-        }
-        let len = RARRAY_LEN(values) as usize;
-        let val_ptr = RARRAY_CONST_PTR(values);
-        let type_id = TraceWriter::ensure_type_id(tracer, TypeKind::Tuple, &class_name);
-        encoder.begin_tuple(type_id, len);
-        for i in 0..len {
-            encode_ruby_value_streaming(recorder, tracer, encoder, *val_ptr.add(i), depth - 1);
+        let members = rb_struct_members(val);
+        let names = symbol_names(members);
+        let type_id = ensure_struct_type_id(recorder, tracer, &class_name, &names);
+        let member_ptr = RARRAY_CONST_PTR(members);
+        encoder.begin_struct(type_id, names.len());
+        recorder.open_compounds += 1;
+        for i in 0..names.len() {
+            let value = rb_struct_getmember(val, rb_sym2id(*member_ptr.add(i)));
+            encode_ruby_value_streaming(recorder, tracer, encoder, value, depth - 1);
         }
+        recorder.open_compounds -= 1;
         encoder.end_compound();
         return;
     }
     if NIL_P(recorder.open_struct_class) {
-        if rb_const_defined(rb_cObject, recorder.id.open_struct_const) != 0 {
-            recorder.open_struct_class = rb_const_get(rb_cObject, recorder.id.open_struct_const);
-        }
+        recorder.open_struct_class =
+            loaded_class_const(recorder, rb_cObject, recorder.id.open_struct_const);
     }
-    if !NIL_P(recorder.open_struct_class) && rb_obj_is_kind_of(val, recorder.open_struct_class) != 0
+    if !pure
+        && !NIL_P(recorder.open_struct_class)
+        && rb_obj_is_kind_of(val, recorder.open_struct_class) != 0
     {
         let h = rb_funcall(val, recorder.id.to_h, 0);
         encode_ruby_value_streaming(recorder, tracer, encoder, h, depth - 1);
         return;
     }
     let class_name = cstr_to_string(rb_obj_classname(val)).unwrap_or_else(|| "Object".to_string());
-    // Generic object: encode instance variables as a tuple.
-    let ivars = rb_funcall(val, recorder.id.instance_variables, 0);
-    if !RB_TYPE_P(ivars, rb_sys::ruby_value_type::RUBY_T_ARRAY) {
-        let text = value_to_string_exception_safe(recorder, val);
-        let type_id = TraceWriter::ensure_type_id(tracer, TypeKind::Raw, &class_name);
-        encoder.write_raw(&text, type_id);
+    // Generic object: encode instance variables as struct fields.  They
+    // are collected before encoding anything, since the ivar table must
+    // not change while `rb_ivar_foreach` walks it.
+    let mut ivars: Vec<(String, VALUE)> = Vec::new();
+    rb_ivar_foreach(
+        val,
+        Some(collect_instance_variable),
+        &mut ivars as *mut Vec<(String, VALUE)> as st_data_t,
+    );
+    if !ivars.is_empty() {
+        let names: Vec<String> = ivars.iter().map(|(name, _)| name.clone()).collect();
+        let type_id = ensure_struct_type_id(recorder, tracer, &class_name, &names);
+        encoder.begin_struct(type_id, ivars.len());
+        recorder.open_compounds += 1;
+        for (_, value) in ivars {
+            encode_ruby_value_streaming(recorder, tracer, encoder, value, depth - 1);
+        }
+        recorder.open_compounds -= 1;
+        encoder.end_compound();
+        return;
+    }
+    let text = value_to_string_exception_safe(recorder, val);
+    let type_id = TraceWriter::ensure_type_id(tracer, TypeKind::Raw, &class_name);
+    encoder.write_raw(&text, type_id);
+}
+
+/// Encode the elements of Array `arr` as a sequence of type `type_id`, at
+/// most `max_elements` of them.
+unsafe fn encode_elements_streaming(
+    recorder: &mut RecorderData,
+    tracer: &mut dyn TraceWriter,
+    encoder: &mut StreamingValueEncoder,
+    arr: VALUE,
+    type_id: TypeId,
+    depth: usize,
+) {
+    let len = RARRAY_LEN(arr) as usize;
+    encode_leading_elements_streaming(recorder, tracer, encoder, arr, len, type_id, depth);
+}
+
+/// Write the leading elements of a `len`-element collection, held in
+/// `arr`, as a sequence of at most `max_elements`, inside `<truncated>`
+/// when some are left out.
+unsafe fn encode_leading_elements_streaming(
+    recorder: &mut RecorderData,
+    tracer: &mut dyn TraceWriter,
+    encoder: &mut StreamingValueEncoder,
+    arr: VALUE,
+    len: usize,
+    type_id: TypeId,
+    depth: usize,
+) {
+    let shown = (RARRAY_LEN(arr) as usize)
+        .min(len)
+        .min(recorder.options.max_elements);
+    let opened = if shown < len { 2 } else { 1 };
+    if shown < len {
+        begin_truncated(recorder, tracer, encoder, len);
+    }
+    encoder.begin_sequence(type_id, shown);
+    recorder.open_compounds += opened;
+    let ptr = RARRAY_CONST_PTR(arr);
+    for i in 0..shown {
+        encode_ruby_value_streaming(recorder, tracer, encoder, *ptr.add(i), depth - 1);
+    }
+    recorder.open_compounds -= opened;
+    encoder.end_compound();
+    if shown < len {
+        encoder.end_compound();
+    }
+}
+
+/// `rb_hash_foreach` / `rb_ivar_foreach` return value that keeps
+/// iterating.
+const HASH_FOREACH_CONTINUE: c_int = rb_sys::st_retval::ST_CONTINUE as c_int;
+
+/// `rb_hash_foreach` return value that ends the iteration.
+const HASH_FOREACH_STOP: c_int = rb_sys::st_retval::ST_STOP as c_int;
+
+/// Encoder state threaded through `rb_hash_foreach` to `encode_hash_entry`.
+struct HashEntryEncoder<'a> {
+    recorder: &'a mut RecorderData,
+    tracer: &'a mut dyn TraceWriter,
+    encoder: &'a mut StreamingValueEncoder,
+    pair_type_id: TypeId,
+    depth: usize,
+    remaining: usize,
+}
+
+unsafe extern "C" fn encode_hash_entry(key: VALUE, value: VALUE, arg: VALUE) -> c_int {
+    let state = &mut *(arg as *mut HashEntryEncoder);
+    // The element count is already written; never emit more entries than
+    // announced, whether the hash is truncated or a key's `to_s` grew it
+    // mid-iteration.
+    if state.remaining == 0 {
+        return HASH_FOREACH_STOP;
+    }
+    state.remaining -= 1;
+    state.encoder.begin_tuple(state.pair_type_id, 2);
+    state.recorder.open_compounds += 1;
+    encode_ruby_value_streaming(
+        state.recorder,
+        state.tracer,
+        state.encoder,
+        key,
+        state.depth - 1,
+    );
+    encode_ruby_value_streaming(
+        state.recorder,
+        state.tracer,
+        state.encoder,
+        value,
+        state.depth - 1,
+    );
+    state.recorder.open_compounds -= 1;
+    state.encoder.end_compound();
+    HASH_FOREACH_CONTINUE
+}
+
+/// Encode a Hash as a `TableKind` map whose entries are `(key, value)`
+/// pairs, walking it in place with `rb_hash_foreach` instead of allocating
+/// `to_a`.  Lookup behaviour that changes what the map means is recorded
+/// in the type name: `Hash[compare_by_identity]`, `Hash[default_proc]`.
+unsafe fn encode_hash_streaming(
+    recorder: &mut RecorderData,
+    tracer: &mut dyn TraceWriter,
+    encoder: &mut StreamingValueEncoder,
+    hash: VALUE,
+    depth: usize,
+) {
+    let mut flags = Vec::new();
+    if !recorder.options.pure_capture {
+        // Hash subclasses may override both; a failing call just leaves
+        // its flag out.
+        if funcall_exception_safe(hash, recorder.id.compare_by_identity_p).is_some_and(TEST) {
+            flags.push("compare_by_identity");
+        }
+        if funcall_exception_safe(hash, recorder.id.default_proc).is_some_and(|v| !NIL_P(v)) {
+            flags.push("default_proc");
+        }
+    }
+    let lang_type = if flags.is_empty() {
+        "Hash".to_string()
+    } else {
+        format!("Hash[{}]", flags.join(", "))
+    };
+    let type_id = TraceWriter::ensure_type_id(tracer, TypeKind::TableKind, &lang_type);
+    let pair_type_id = TraceWriter::ensure_type_id(tracer, TypeKind::Tuple, "Pair");
+    let len = RHASH_SIZE(hash);
+    let shown = len.min(recorder.options.max_elements);
+    let opened = if shown < len { 2 } else { 1 };
+    if shown < len {
+        begin_truncated(recorder, tracer, encoder, len);
+    }
+    encoder.begin_sequence(type_id, shown);
+    recorder.open_compounds += opened;
+    let mut state = HashEntryEncoder {
+        recorder,
+        tracer,
+        encoder,
+        pair_type_id,
+        depth,
+        remaining: shown,
+    };
+    rb_hash_foreach(
+        hash,
+        Some(encode_hash_entry),
+        &mut state as *mut HashEntryEncoder as VALUE,
+    );
+    // Pad if entries were deleted mid-iteration so the sequence still
+    // holds the announced number of elements.
+    for _ in 0..state.remaining {
+        state.encoder.write_none(state.recorder.error_type_id);
+    }
+    state.recorder.open_compounds -= opened;
+    state.encoder.end_compound();
+    if shown < len {
+        state.encoder.end_compound();
+    }
+}
+
+/// `rb_ivar_foreach` callback collecting an object's instance variables as
+/// `(name without "@", value)`.  Class variables and the interpreter's
+/// own hidden ivars (exception messages, ...) are skipped, matching
+/// `instance_variables`.
+unsafe extern "C" fn collect_instance_variable(id: ID, value: VALUE, arg: st_data_t) -> c_int {
+    let ivars = &mut *(arg as *mut Vec<(String, VALUE)>);
+    let name = cstr_to_string(rb_id2name(id)).unwrap_or_default();
+    if let Some(field) = name.strip_prefix('@') {
+        if !field.starts_with('@') {
+            ivars.push((field.to_string(), value));
+        }
+    }
+    HASH_FOREACH_CONTINUE
+}
+
+/// Encode an IO (File, Socket, pipe, ...) as a struct of its descriptor
+/// state: `{fd, path, mode, closed, pos}`, read through Ruby's public IO
+/// functions, so nothing is read, written or flushed.  `path` and `mode`
+/// need Ruby 3.3 (`rb_io_path`, `rb_io_mode`) and are nil before it; `fd`
+/// and `mode` are nil once the IO is closed.  `pos` is only asked of IOs
+/// open read-only (see `io_pos`).  `eof?` is left out: it reads ahead
+/// from the descriptor and blocks on pipes and terminals.
+unsafe fn encode_io_streaming(
+    recorder: &mut RecorderData,
+    tracer: &mut dyn TraceWriter,
+    encoder: &mut StreamingValueEncoder,
+    io: VALUE,
+) {
+    let class_name = cstr_to_string(rb_obj_classname(io)).unwrap_or_else(|| "IO".to_string());
+    let fields = ["fd", "path", "mode", "closed", "pos"].map(String::from);
+    let type_id = ensure_struct_type_id(recorder, tracer, &class_name, &fields);
+    encoder.begin_struct(type_id, fields.len());
+    let fd = io_fd(io);
+    match fd {
+        Some(fd) => encoder.write_int(fd, recorder.int_type_id),
+        None => encoder.write_none(recorder.error_type_id),
+    }
+    match io_path(io) {
+        Some(path) => encoder.write_string(&path, recorder.string_type_id),
+        None => encoder.write_none(recorder.error_type_id),
+    }
+    let mode = fd.and_then(|_| io_mode(io));
+    match mode {
+        Some(mode) => encoder.write_string(&io_mode_string(mode), recorder.string_type_id),
+        None => encoder.write_none(recorder.error_type_id),
+    }
+    encoder.write_bool(fd.is_none(), recorder.bool_type_id);
+    match mode.and_then(|mode| io_pos(recorder, io, mode)) {
+        Some(pos) => encoder.write_int(pos, recorder.int_type_id),
+        None => encoder.write_none(recorder.error_type_id),
+    }
+    encoder.end_compound();
+}
+
+/// `io.pos` for an open IO whose `FMODE_*` flags are `mode`, or `None`
+/// when it is writable (`IO#pos` flushes pending writes first), not
+/// seekable (pipes, sockets and terminals raise) or under `pure_capture`.
+unsafe fn io_pos(recorder: &RecorderData, io: VALUE, mode: c_int) -> Option<i64> {
+    if recorder.options.pure_capture || mode & rb_sys::FMODE_WRITABLE as c_int != 0 {
+        return None;
+    }
+    funcall_exception_safe(io, recorder.id.pos).and_then(|pos| fixnum_value(pos))
+}
+
+unsafe extern "C" fn call_io_descriptor(io: VALUE) -> VALUE {
+    rb_int2inum(rb_io_descriptor(io) as isize)
+}
+
+/// The file descriptor of `io`, or `None` once it is closed
+/// (`rb_io_descriptor` raises then).
+unsafe fn io_fd(io: VALUE) -> Option<i64> {
+    let mut state: c_int = 0;
+    let fd = rb_protect(Some(call_io_descriptor), io, &mut state);
+    if state != 0 {
+        rb_set_errinfo(Qnil.into());
+        None
+    } else {
+        fixnum_value(fd)
+    }
+}
+
+#[cfg(ruby_gte_3_3)]
+unsafe fn io_path(io: VALUE) -> Option<String> {
+    string_value(rb_sys::rb_io_path(io)).map(|path| rstring_lossy(path))
+}
+
+#[cfg(not(ruby_gte_3_3))]
+unsafe fn io_path(_io: VALUE) -> Option<String> {
+    None
+}
+
+/// The `FMODE_*` flags of open IO `io`.
+#[cfg(ruby_gte_3_3)]
+unsafe fn io_mode(io: VALUE) -> Option<c_int> {
+    Some(rb_sys::rb_io_mode(io) as c_int)
+}
+
+#[cfg(not(ruby_gte_3_3))]
+unsafe fn io_mode(_io: VALUE) -> Option<c_int> {
+    None
+}
+
+/// The `File.open` mode string matching `FMODE_*` flags, e.g. `r`, `w`,
+/// `a+`, `rb`.
+fn io_mode_string(mode: c_int) -> String {
+    let flag = |fmode: u32| mode & fmode as c_int != 0;
+    let readable = flag(rb_sys::FMODE_READABLE);
+    let writable = flag(rb_sys::FMODE_WRITABLE);
+    let mut text = if flag(rb_sys::FMODE_APPEND) {
+        "a"
+    } else if writable && (!readable || flag(rb_sys::FMODE_TRUNC)) {
+        "w"
+    } else {
+        "r"
+    }
+    .to_string();
+    if readable && writable {
+        text.push('+');
+    }
+    if flag(rb_sys::FMODE_BINMODE) {
+        text.push('b');
+    }
+    text
+}
+
+/// Leading fields of the interpreter's `struct enumerator` (enumerator.c),
+/// laid out the same since Ruby 2.0: the receiver, the method it iterates
+/// with and that method's arguments (an Array, or 0 when there are none).
+/// Ruby has no method or C function that returns them.
+#[repr(C)]
+struct EnumeratorHead {
+    receiver: VALUE,
+    method: ID,
+    args: VALUE,
+}
+
+unsafe extern "C" fn call_check_typeddata(arg: VALUE) -> VALUE {
+    let data = &*(arg as *const (VALUE, *const rb_data_type_t));
+    rb_check_typeddata(data.0, data.1) as VALUE
+}
+
+/// The `struct enumerator` behind `enumerator`, or `None` for enumerators
+/// backed by other data (`Enumerator::Chain`, `Enumerator::Product`, ...).
+/// The data pointer is taken with `rb_check_typeddata`, protected, so a
+/// type mismatch is never raised into the program.
+unsafe fn enumerator_head(enumerator: VALUE) -> Option<*const EnumeratorHead> {
+    if !RTYPEDDATA_P(enumerator) {
+        return None;
+    }
+    let ty = (*(enumerator as *const RTypedData)).type_;
+    if ty.is_null() || cstr_to_string((*ty).wrap_struct_name).as_deref() != Some("enumerator") {
+        return None;
+    }
+    let mut state: c_int = 0;
+    let data = (enumerator, ty);
+    let head = rb_protect(
+        Some(call_check_typeddata),
+        &data as *const _ as VALUE,
+        &mut state,
+    );
+    if state != 0 {
+        rb_set_errinfo(Qnil.into());
+        return None;
+    }
+    let head = head as *const EnumeratorHead;
+    (!head.is_null()).then_some(head)
+}
+
+/// Encode an Enumerator (or `Enumerator::Lazy`, ...) as
+/// `{receiver, method, args}` without iterating it: no method is called
+/// on it, so neither `next` nor a size block runs.  The receiver and
+/// arguments are encoded like any value, so the capture limits apply to
+/// them.  Returns false, leaving `enumerator` to the generic encoding,
+/// when it is not backed by `struct enumerator`.
+unsafe fn encode_enumerator_streaming(
+    recorder: &mut RecorderData,
+    tracer: &mut dyn TraceWriter,
+    encoder: &mut StreamingValueEncoder,
+    enumerator: VALUE,
+    depth: usize,
+) -> bool {
+    let Some(head) = enumerator_head(enumerator) else {
+        return false;
+    };
+    let (receiver, method, args) = ((*head).receiver, (*head).method, (*head).args);
+    let class_name =
+        cstr_to_string(rb_obj_classname(enumerator)).unwrap_or_else(|| "Enumerator".to_string());
+
+    let fields = ["receiver", "method", "args"].map(String::from);
+    let type_id = ensure_struct_type_id(recorder, tracer, &class_name, &fields);
+    encoder.begin_struct(type_id, fields.len());
+    recorder.open_compounds += 1;
+    encode_ruby_value_streaming(recorder, tracer, encoder, receiver, depth - 1);
+    match cstr_to_string(rb_id2name(method)) {
+        Some(method) => encoder.write_string(&method, recorder.symbol_type_id),
+        None => encoder.write_none(recorder.error_type_id),
+    }
+    if RB_TYPE_P(args, rb_sys::ruby_value_type::RUBY_T_ARRAY) {
+        encode_ruby_value_streaming(recorder, tracer, encoder, args, depth - 1);
+    } else {
+        let args_type_id = TraceWriter::ensure_type_id(tracer, TypeKind::Seq, "Array");
+        encoder.begin_sequence(args_type_id, 0);
+        encoder.end_compound();
+    }
+    recorder.open_compounds -= 1;
+    encoder.end_compound();
+    true
+}
+
+/// Encode a point in time as a tuple typed `type_name`:
+/// `(seconds since the epoch, nanoseconds, UTC offset in seconds, zone)`.
+unsafe fn encode_time_streaming(
+    recorder: &mut RecorderData,
+    tracer: &mut dyn TraceWriter,
+    encoder: &mut StreamingValueEncoder,
+    type_name: &str,
+    (sec, nsec): (i64, i64),
+    utc_offset: VALUE,
+    zone: VALUE,
+) {
+    let type_id = TraceWriter::ensure_type_id(tracer, TypeKind::Tuple, type_name);
+    encoder.begin_tuple(type_id, 4);
+    encoder.write_int(sec, recorder.int_type_id);
+    encoder.write_int(nsec, recorder.int_type_id);
+    if let Some(utc_offset) = fixnum_value(utc_offset) {
+        encoder.write_int(utc_offset, recorder.int_type_id);
+    } else {
+        encoder.write_none(recorder.error_type_id);
+    }
+    if RB_TYPE_P(zone, rb_sys::ruby_value_type::RUBY_T_STRING) {
+        encoder.write_string(&rstring_lossy(zone), recorder.string_type_id);
+    } else {
+        encoder.write_none(recorder.error_type_id);
+    }
+    encoder.end_compound();
+}
+
+/// Standard-library value types, each resolved once its library is
+/// loaded: `Date` / `DateTime` as ISO 8601 strings,
+/// `ActiveSupport::TimeWithZone` like `Time`, and `Pathname`, `URI` and
+/// `IPAddr` (with its prefix length) as strings typed after their class.
+/// The methods involved can be overridden, so they run under `rb_protect`;
+/// returns false when `val` is none of these or one of them fails, leaving
+/// `val` to the generic object encoding.
+unsafe fn encode_stdlib_value_streaming(
+    recorder: &mut RecorderData,
+    tracer: &mut dyn TraceWriter,
+    encoder: &mut StreamingValueEncoder,
+    val: VALUE,
+) -> bool {
+    if NIL_P(recorder.date_class) {
+        recorder.date_class = loaded_class_const(recorder, rb_cObject, recorder.id.date_const);
+    }
+    if NIL_P(recorder.date_time_class) {
+        recorder.date_time_class =
+            loaded_class_const(recorder, rb_cObject, recorder.id.date_time_const);
+    }
+    if NIL_P(recorder.time_with_zone_class) {
+        recorder.time_with_zone_class = loaded_nested_class_const(
+            recorder,
+            recorder.id.active_support_const,
+            recorder.id.time_with_zone_const,
+        );
+    }
+    if NIL_P(recorder.pathname_class) {
+        recorder.pathname_class =
+            loaded_class_const(recorder, rb_cObject, recorder.id.pathname_const);
+    }
+    if NIL_P(recorder.uri_class) {
+        recorder.uri_class =
+            loaded_nested_class_const(recorder, recorder.id.uri_const, recorder.id.generic_const);
+    }
+    if NIL_P(recorder.ip_addr_class) {
+        recorder.ip_addr_class =
+            loaded_class_const(recorder, rb_cObject, recorder.id.ip_addr_const);
+    }
+    let is_a = |class: VALUE| !NIL_P(class) && rb_obj_is_kind_of(val, class) != 0;
+
+    let call = |id: ID| funcall_exception_safe(val, id);
+
+    let (lang_type, text) = if is_a(recorder.date_time_class) {
+        // Nanosecond precision, with the offset: `2024-05-01T12:30:00.000000000+02:00`.
+        let digits = rb_int2inum(9);
+        (
+            "DateTime",
+            funcall_args_exception_safe(val, recorder.id.iso8601, &[digits]),
+        )
+    } else if is_a(recorder.date_class) {
+        ("Date", call(recorder.id.iso8601))
+    } else if is_a(recorder.time_with_zone_class) {
+        let sec = call(recorder.id.to_i).and_then(|v| fixnum_value(v));
+        let nsec = call(recorder.id.nsec).and_then(|v| fixnum_value(v));
+        let utc_offset = call(recorder.id.utc_offset);
+        let zone = call(recorder.id.zone);
+        let (Some(sec), Some(nsec), Some(utc_offset), Some(zone)) = (sec, nsec, utc_offset, zone)
+        else {
+            return false;
+        };
+        encode_time_streaming(
+            recorder,
+            tracer,
+            encoder,
+            "ActiveSupport::TimeWithZone",
+            (sec, nsec),
+            utc_offset,
+            zone,
+        );
+        return true;
+    } else if is_a(recorder.pathname_class) {
+        ("Pathname", call(recorder.id.to_s))
+    } else if is_a(recorder.uri_class) {
+        ("URI", call(recorder.id.to_s))
+    } else if is_a(recorder.ip_addr_class) {
+        let addr = call(recorder.id.to_s).and_then(|v| string_value(v));
+        let prefix = call(recorder.id.prefix).and_then(|v| fixnum_value(v));
+        let (Some(addr), Some(prefix)) = (addr, prefix) else {
+            return false;
+        };
+        let type_id = TraceWriter::ensure_type_id(tracer, TypeKind::String, "IPAddr");
+        encoder.write_string(&format!("{}/{}", rstring_lossy(addr), prefix), type_id);
+        return true;
+    } else {
+        return false;
+    };
+    let Some(text) = text.and_then(|v| string_value(v)) else {
+        return false;
+    };
+    let type_id = TraceWriter::ensure_type_id(tracer, TypeKind::String, lang_type);
+    encoder.write_string(&rstring_lossy(text), type_id);
+    true
+}
+
+/// The constant `id` of `scope` (`rb_cObject` for a top-level one such as
+/// `Set`), or `nil` while it is undefined.  With `pure_capture`, a
+/// constant still registered as an autoload is left alone too: resolving
+/// it would load library code from inside the event hook.
+unsafe fn loaded_class_const(recorder: &RecorderData, scope: VALUE, id: ID) -> VALUE {
+    if rb_const_defined(scope, id) == 0
+        || (recorder.options.pure_capture && !NIL_P(rb_autoload_p(scope, id)))
+    {
+        return Qnil.into();
+    }
+    rb_const_get(scope, id)
+}
+
+/// `Outer::Inner` (e.g. `URI::Generic`), or `nil` while either is
+/// undefined.
+unsafe fn loaded_nested_class_const(recorder: &RecorderData, outer: ID, inner: ID) -> VALUE {
+    let scope = loaded_class_const(recorder, rb_cObject, outer);
+    if RB_TYPE_P(scope, rb_sys::ruby_value_type::RUBY_T_MODULE)
+        || RB_TYPE_P(scope, rb_sys::ruby_value_type::RUBY_T_CLASS)
+    {
+        loaded_class_const(recorder, scope, inner)
+    } else {
+        Qnil.into()
+    }
+}
+
+/// Names of an array of symbols such as a struct's `members`.
+unsafe fn symbol_names(symbols: VALUE) -> Vec<String> {
+    let len = RARRAY_LEN(symbols) as usize;
+    let ptr = RARRAY_CONST_PTR(symbols);
+    (0..len)
+        .map(|i| cstr_to_string(rb_id2name(rb_sym2id(*ptr.add(i)))).unwrap_or_default())
+        .collect()
+}
+
+/// Type id of a struct type named after `class_name` with the given
+/// fields.  Field values are encoded with their own types, so fields are
+/// declared untyped.  The writer keys types by name: the first field
+/// layout seen for a class takes the bare class name, and later layouts
+/// (objects whose ivars are assigned lazily) get the field list appended,
+/// e.g. `Point {x, y, label}`.
+unsafe fn ensure_struct_type_id(
+    recorder: &mut RecorderData,
+    tracer: &mut dyn TraceWriter,
+    class_name: &str,
+    field_names: &[String],
+) -> TypeId {
+    let field_type_id = recorder.error_type_id;
+    let layouts = recorder
+        .struct_types
+        .entry(class_name.to_string())
+        .or_default();
+    if let Some((_, type_id)) = layouts.iter().find(|(names, _)| names == field_names) {
+        return *type_id;
+    }
+    let lang_type = if layouts.is_empty() {
+        class_name.to_string()
+    } else {
+        format!("{} {{{}}}", class_name, field_names.join(", "))
+    };
+    let fields = field_names
+        .iter()
+        .map(|name| FieldTypeRecord {
+            name: name.clone(),
+            type_id: field_type_id,
+        })
+        .collect();
+    let type_id = TraceWriter::ensure_raw_type_id(
+        tracer,
+        TypeRecord {
+            kind: TypeKind::Struct,
+            lang_type,
+            specific_info: TypeSpecificInfo::Struct { fields },
+        },
+    );
+    layouts.push((field_names.to_vec(), type_id));
+    type_id
+}
+
+/// Encode an exception as a struct named after its class with the fields
+/// `message`, `backtrace`, `cause` and then its own instance variables.
+/// The backtrace is a sequence of `Location` `(path, line)` tuples taken
+/// from `backtrace_locations`, subject to the capture limits, and `cause`
+/// is encoded the same way one level deeper (or nil at the end of the
+/// chain).  Raise / rescue events and variables holding an exception
+/// share this encoding.
+unsafe fn encode_exception_streaming(
+    recorder: &mut RecorderData,
+    tracer: &mut dyn TraceWriter,
+    encoder: &mut StreamingValueEncoder,
+    exc: VALUE,
+    depth: usize,
+) {
+    let class_name =
+        cstr_to_string(rb_obj_classname(exc)).unwrap_or_else(|| "Exception".to_string());
+    let message = exception_message(recorder, exc);
+    let mut ivars: Vec<(String, VALUE)> = Vec::new();
+    rb_ivar_foreach(
+        exc,
+        Some(collect_instance_variable),
+        &mut ivars as *mut Vec<(String, VALUE)> as st_data_t,
+    );
+    let mut fields = ["message", "backtrace", "cause"].map(String::from).to_vec();
+    fields.extend(ivars.iter().map(|(name, _)| name.clone()));
+    let type_id = ensure_struct_type_id(recorder, tracer, &class_name, &fields);
+    encoder.begin_struct(type_id, fields.len());
+    encoder.write_string(&message, recorder.string_type_id);
+
+    // `backtrace_locations` and `cause` are Ruby methods that exception
+    // classes may override, so they run under `rb_protect`; `pure_capture`
+    // reads the cause from the exception itself and has no backtrace.
+    let locations = if recorder.options.pure_capture {
+        Qnil.into()
+    } else {
+        funcall_exception_safe(exc, recorder.id.backtrace_locations).unwrap_or(Qnil.into())
+    };
+    let backtrace_type_id = TraceWriter::ensure_type_id(tracer, TypeKind::Seq, "Backtrace");
+    if RB_TYPE_P(locations, rb_sys::ruby_value_type::RUBY_T_ARRAY) {
+        // Cut to `max_elements` like any other sequence; only the frames
+        // shown are asked for their path and line.
+        let len = RARRAY_LEN(locations) as usize;
+        let shown = len.min(recorder.options.max_elements);
+        let ptr = RARRAY_CONST_PTR(locations);
+        let location_type_id = TraceWriter::ensure_type_id(tracer, TypeKind::Tuple, "Location");
+        if shown < len {
+            begin_truncated(recorder, tracer, encoder, len);
+        }
+        encoder.begin_sequence(backtrace_type_id, shown);
+        for i in 0..shown {
+            let location = *ptr.add(i);
+            let path =
+                funcall_exception_safe(location, recorder.id.path).and_then(|v| string_value(v));
+            let lineno =
+                funcall_exception_safe(location, recorder.id.lineno).and_then(|v| fixnum_value(v));
+            encoder.begin_tuple(location_type_id, 2);
+            match path {
+                Some(path) => encode_string_streaming(recorder, tracer, encoder, path),
+                None => encoder.write_none(recorder.error_type_id),
+            }
+            match lineno {
+                Some(lineno) => encoder.write_int(lineno, recorder.int_type_id),
+                None => encoder.write_none(recorder.error_type_id),
+            }
+            encoder.end_compound();
+        }
+        encoder.end_compound();
+        if shown < len {
+            encoder.end_compound();
+        }
+    } else {
+        encoder.begin_sequence(backtrace_type_id, 0);
+        encoder.end_compound();
+    }
+
+    let cause = if recorder.options.pure_capture {
+        rb_attr_get(exc, recorder.id.cause)
+    } else {
+        funcall_exception_safe(exc, recorder.id.cause).unwrap_or(Qnil.into())
+    };
+    recorder.open_compounds += 1;
+    if rb_obj_is_kind_of(cause, rb_eException) == 0 {
+        encoder.write_none(recorder.error_type_id);
+    } else {
+        // Each link of the chain is one level deeper, so `max_depth`, the
+        // nesting limit and back-references bound it like any value.
+        encode_ruby_value_streaming(recorder, tracer, encoder, cause, depth - 1);
+    }
+    for (_, value) in ivars {
+        encode_ruby_value_streaming(recorder, tracer, encoder, value, depth - 1);
+    }
+    recorder.open_compounds -= 1;
+    encoder.end_compound();
+}
+
+/// The message of `exc`: its `to_s`, or with `pure_capture` the message
+/// it was raised with (the hidden `mesg` ivar), falling back to the class
+/// name like `Exception#to_s` does.
+unsafe fn exception_message(recorder: &RecorderData, exc: VALUE) -> String {
+    if !recorder.options.pure_capture {
+        return value_to_string_exception_safe(recorder, exc);
+    }
+    let mesg = rb_attr_get(exc, recorder.id.mesg);
+    if RB_TYPE_P(mesg, rb_sys::ruby_value_type::RUBY_T_STRING) {
+        rstring_lossy(mesg)
+    } else {
+        cstr_to_string(rb_obj_classname(exc)).unwrap_or_default()
+    }
+}
+
+/// Register `exc` as the `<exception>` variable of the current step.
+unsafe fn record_exception_streaming(
+    recorder: &mut RecorderData,
+    tracer: &mut dyn TraceWriter,
+    encoder: &mut StreamingValueEncoder,
+    exc: VALUE,
+) {
+    begin_value_encoding(recorder, encoder);
+    let depth = recorder.options.max_depth;
+    // On the path like any value's root, so a `cause` chain leading back
+    // to `exc` ends in a back-reference.
+    recorder.encoding_path.push(exc);
+    encode_exception_streaming(recorder, tracer, encoder, exc, depth);
+    recorder.encoding_path.pop();
+    let cbor = encoder.get_bytes_copy();
+    TraceWriter::register_variable_cbor(tracer, "<exception>", &cbor);
+}
+
+/// Start encoding a new top-level value: clears the encoder and the
+/// per-value identity tracking (back-references never cross values).
+fn begin_value_encoding(recorder: &mut RecorderData, encoder: &mut StreamingValueEncoder) {
+    encoder.reset();
+    recorder.encoding_path.clear();
+    recorder.encoded_objects.clear();
+    recorder.open_compounds = 0;
+}
+
+/// Encode a single Ruby value to CBOR bytes, resetting the encoder first.
+/// Returns a copy of the CBOR bytes suitable for passing to
+/// `register_variable_cbor` or `register_return_cbor`.
+unsafe fn encode_ruby_value_to_cbor(
+    recorder: &mut RecorderData,
+    tracer: &mut dyn TraceWriter,
+    encoder: &mut StreamingValueEncoder,
+    val: VALUE,
+) -> Vec<u8> {
+    begin_value_encoding(recorder, encoder);
+    let depth = recorder.options.max_depth;
+    encode_ruby_value_streaming(recorder, tracer, encoder, val, depth);
+    encoder.get_bytes_copy()
+}
+
+/// Streaming variant of `record_variables`. Encodes Ruby local variables
+/// directly to CBOR bytes and registers them via `register_variable_cbor`,
+/// avoiding intermediate `ValueRecord` tree allocations.
+unsafe fn record_variables_streaming(
+    recorder: &mut RecorderData,
+    tracer: &mut dyn TraceWriter,
+    encoder: &mut StreamingValueEncoder,
+    thread_id: u64,
+    binding: VALUE,
+) {
+    let vars = rb_funcall(binding, recorder.id.local_variables, 0);
+    if !RB_TYPE_P(vars, rb_sys::ruby_value_type::RUBY_T_ARRAY) {
         return;
     }
-    let len = RARRAY_LEN(ivars) as usize;
-    let ptr = RARRAY_CONST_PTR(ivars);
-    if len > 0 {
-        let type_id = TraceWriter::ensure_type_id(tracer, TypeKind::Tuple, &class_name);
-        encoder.begin_tuple(type_id, len);
-        for i in 0..len {
-            let sym = *ptr.add(i);
-            let value = rb_funcall(val, recorder.id.instance_variable_get, 1, sym);
-            encode_ruby_value_streaming(recorder, tracer, encoder, value, depth - 1);
-        }
-        encoder.end_compound();
-        return;
+    let len = RARRAY_LEN(vars) as usize;
+    let ptr = RARRAY_CONST_PTR(vars);
+    let incremental = recorder.options.incremental_locals;
+    let depth = recorder.open_frames.get(&thread_id).copied().unwrap_or(0);
+    if incremental {
+        recorder
+            .frame_locals
+            .entry(thread_id)
+            .or_default()
+            .resize_with(depth + 1, HashMap::new);
+    }
+    let mut unchanged = Vec::new();
+    for i in 0..len {
+        let sym = *ptr.add(i);
+        let name = cstr_to_string(rb_id2name(rb_sym2id(sym))).unwrap_or_default();
+        let value = rb_funcall(binding, recorder.id.local_variable_get, 1, sym);
+        // Values are encoded even when they turn out unchanged: comparing
+        // the bytes is the only check that sees in-place mutation.
+        let cbor = encode_ruby_value_to_cbor(recorder, tracer, encoder, value);
+        if incremental {
+            let digest = cbor_digest(&cbor);
+            let shadow = &mut recorder.frame_locals.get_mut(&thread_id).unwrap()[depth];
+            if shadow.get(&name) == Some(&digest) {
+                unchanged.push(name);
+                continue;
+            }
+            shadow.insert(name.clone(), digest);
+        }
+        TraceWriter::register_variable_cbor(tracer, &name, &cbor);
+    }
+    if !unchanged.is_empty() {
+        register_unchanged_locals(recorder, tracer, encoder, &unchanged);
+    }
+}
+
+/// Cheap fingerprint of an encoded value for the `incremental_locals`
+/// shadow; only compared against earlier values of the same local.
+fn cbor_digest(cbor: &[u8]) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    cbor.hash(&mut hasher);
+    hasher.finish()
+}
+
+/// Register the `<unchanged>` marker: a Seq of the local names skipped at
+/// this step because their value is the one last registered for them in
+/// the current call.  Readers rebuild the full variable set by carrying
+/// those values forward.
+fn register_unchanged_locals(
+    recorder: &RecorderData,
+    tracer: &mut dyn TraceWriter,
+    encoder: &mut StreamingValueEncoder,
+    names: &[String],
+) {
+    let type_id = TraceWriter::ensure_type_id(tracer, TypeKind::Seq, "<unchanged>");
+    encoder.reset();
+    encoder.begin_sequence(type_id, names.len());
+    for name in names {
+        encoder.write_string(name, recorder.string_type_id);
+    }
+    encoder.end_compound();
+    TraceWriter::register_variable_cbor(tracer, "<unchanged>", &encoder.get_bytes_copy());
+}
+
+// Legacy record_variables has been removed — replaced by
+// record_variables_streaming (M59).
+
+/// Encodes each parameter named in `params_ary` (the `[[kind, name], ...]`
+/// shape returned by `Method#parameters` / `Proc#parameters`) directly to
+/// CBOR bytes using the streaming encoder, registers it via
+/// `register_variable_cbor`, and returns (name, variable_id) pairs for
+/// constructing `CallRecord.args`.
+unsafe fn register_params_streaming(
+    recorder: &mut RecorderData,
+    tracer: &mut dyn TraceWriter,
+    encoder: &mut StreamingValueEncoder,
+    binding: VALUE,
+    params_ary: VALUE,
+) -> Vec<FullValueRecord> {
+    if !RB_TYPE_P(params_ary, rb_sys::ruby_value_type::RUBY_T_ARRAY) {
+        return Vec::new();
+    }
+    let params_len = RARRAY_LEN(params_ary) as usize;
+    let params_ptr = RARRAY_CONST_PTR(params_ary);
+    let mut result = Vec::with_capacity(params_len);
+    for i in 0..params_len {
+        let pair = *params_ptr.add(i);
+        if !RB_TYPE_P(pair, rb_sys::ruby_value_type::RUBY_T_ARRAY) || RARRAY_LEN(pair) < 1 {
+            continue;
+        }
+        let pair_ptr = RARRAY_CONST_PTR(pair);
+        let kind = rb_sym2id(*pair_ptr.add(0));
+        // Anonymous `*`, `**` and `&` parameters (and the ones `...`
+        // expands to) are reported without a name, or named after the
+        // sigil itself; neither can be read back from the binding.
+        let name = if RARRAY_LEN(pair) < 2 || NIL_P(*pair_ptr.add(1)) {
+            None
+        } else {
+            cstr_to_string(rb_id2name(rb_sym2id(*pair_ptr.add(1))))
+                .filter(|name| !matches!(name.as_str(), "*" | "**" | "&"))
+        };
+        // Unnamed positional parameters (destructuring `|(a, b)|`) and
+        // `**nil` bind nothing that can be read back; skip them.
+        if name.is_none()
+            && kind != recorder.id.param_rest
+            && kind != recorder.id.param_keyrest
+            && kind != recorder.id.param_block
+        {
+            continue;
+        }
+        let arg_name = param_arg_name(&recorder.id, kind, name.as_deref().unwrap_or(""));
+        // The binding is the frame's own, but a parameter that is not
+        // readable from it must not raise into the traced program.
+        let value = name.as_ref().and_then(|_| {
+            funcall_args_exception_safe(
+                binding,
+                recorder.id.local_variable_get,
+                &[*pair_ptr.add(1)],
+            )
+        });
+        let cbor = match (&name, value) {
+            (Some(name), Some(value)) => {
+                let cbor = encode_ruby_value_to_cbor(recorder, tracer, encoder, value);
+                TraceWriter::register_variable_cbor(tracer, name, &cbor);
+                cbor
+            }
+            _ => {
+                encoder.reset();
+                encoder.write_none(recorder.error_type_id);
+                encoder.get_bytes_copy()
+            }
+        };
+        // Stage the same CBOR bytes on the writer's pending-call-args
+        // buffer so the next `register_call` attaches them to the
+        // call record's `args` field.  Without this the CTFS call
+        // record has empty `args` and the frontend's calltrace pane
+        // renders calls as `f()` instead of `f(name=value)`.  The arg
+        // name carries the parameter kind (see `param_arg_name`).
+        TraceWriter::register_call_arg(tracer, &arg_name, &cbor);
+        let var_id = TraceWriter::ensure_variable_id(tracer, &arg_name);
+        // We still need a ValueRecord for FullValueRecord in CallRecord.args.
+        // Use a lightweight None sentinel — the CBOR data is already registered
+        // and the reader will use CBOR for the actual value.
+        result.push(FullValueRecord {
+            variable_id: var_id,
+            value: ValueRecord::None {
+                type_id: recorder.error_type_id,
+            },
+        });
+    }
+    result
+}
+
+/// Call-argument name for a parameter, decorated with its kind the way it
+/// is written in a signature so the calltrace can render
+/// `f(a, *rest, key:, **opts, &blk)`.  Required and optional positional
+/// parameters keep their bare name; anonymous ones reduce to the sigil.
+fn param_arg_name(id: &InternedSymbols, kind: ID, name: &str) -> String {
+    if kind == id.param_rest {
+        format!("*{}", name)
+    } else if kind == id.param_keyrest {
+        format!("**{}", name)
+    } else if kind == id.param_block {
+        format!("&{}", name)
+    } else if kind == id.param_keyreq || kind == id.param_key {
+        format!("{}:", name)
+    } else {
+        name.to_string()
+    }
+}
+
+// Legacy collect_parameter_values / register_parameter_values have been
+// removed — replaced by register_params_streaming (M59).
+
+/// Encode the receiver of a call, register it as the `self` variable and
+/// stage it as the first call argument.  Returns the `FullValueRecord`
+/// placeholder for `CallRecord.args`.  Receivers of C functions
+/// (`class_only`) are just their class name, since encoding every
+/// `arr.each` or `str.gsub` receiver would cost more than the call; others
+/// are their `to_s` text unless `structured_self` asks for the full value
+/// encoding.
+unsafe fn register_self_arg(
+    recorder: &mut RecorderData,
+    tracer: &mut dyn TraceWriter,
+    encoder: &mut StreamingValueEncoder,
+    self_val: VALUE,
+    class_only: bool,
+) -> FullValueRecord {
+    let self_cbor = if recorder.options.structured_self && !class_only {
+        encode_ruby_value_to_cbor(recorder, tracer, encoder, self_val)
+    } else {
+        let class_name =
+            cstr_to_string(rb_obj_classname(self_val)).unwrap_or_else(|| "Object".to_string());
+        let text = if class_only {
+            class_name.clone()
+        } else {
+            value_to_string_exception_safe(recorder, self_val)
+        };
+        let self_type = TraceWriter::ensure_type_id(tracer, TypeKind::Raw, &class_name);
+        encoder.reset();
+        encoder.write_raw(&text, self_type);
+        encoder.get_bytes_copy()
+    };
+    TraceWriter::register_variable_cbor(tracer, "self", &self_cbor);
+    // Also stage `self` as the first call arg so the frontend's
+    // calltrace pane can render the receiver alongside the method
+    // name (matches the Ruby convention of method calls being
+    // dispatched on a receiver).
+    TraceWriter::register_call_arg(tracer, "self", &self_cbor);
+
+    let self_var_id = TraceWriter::ensure_variable_id(tracer, "self");
+    FullValueRecord {
+        variable_id: self_var_id,
+        value: ValueRecord::None {
+            type_id: recorder.error_type_id,
+        },
+    }
+}
+
+/// The module that defines the method running in the current frame.
+/// Methods mixed in with `include` / `prepend` report the module itself
+/// rather than its internal iclass; singleton methods report the
+/// singleton class.  Falls back to the receiver's class when the VM has
+/// no method entry for the frame.
+unsafe fn method_owner(arg: *mut rb_trace_arg_t, self_val: VALUE) -> VALUE {
+    let owner = rb_tracearg_defined_class(arg);
+    if NIL_P(owner) {
+        rb_obj_class(self_val)
+    } else {
+        owner
+    }
+}
+
+/// The flag marking a singleton class.
+const FL_SINGLETON: VALUE = rb_sys::ruby_fl_type::RUBY_FL_SINGLETON as VALUE;
+
+/// Whether `klass` is a singleton class, read from its flags rather than
+/// by calling `singleton_class?`.
+unsafe fn singleton_class_p(klass: VALUE) -> bool {
+    RB_TYPE_P(klass, rb_sys::ruby_value_type::RUBY_T_CLASS)
+        && (*(klass as *const RBasic)).flags & FL_SINGLETON != 0
+}
+
+/// Function name recorded for a method call, named after the method's
+/// owner: `Owner#method` for instance methods (including inherited and
+/// mixed-in ones), `Owner.method` for singleton / class methods, and the
+/// bare method name for top-level methods, which Ruby defines on Object.
+unsafe fn qualified_method_name(owner: VALUE, self_val: VALUE, mid: ID) -> String {
+    let name = cstr_to_string(rb_id2name(mid)).unwrap_or_default();
+    if owner == rb_cObject {
+        return name;
+    }
+    let (owner_name, separator) = if singleton_class_p(owner) {
+        let attached = rb_class_attached_object(owner);
+        let attached_name = if RB_TYPE_P(attached, rb_sys::ruby_value_type::RUBY_T_CLASS)
+            || RB_TYPE_P(attached, rb_sys::ruby_value_type::RUBY_T_MODULE)
+        {
+            rb_mod_name(attached)
+        } else {
+            rb_class_name(rb_obj_class(attached))
+        };
+        (attached_name, ".")
+    } else {
+        (rb_mod_name(owner), "#")
+    };
+    // Anonymous modules have no name; use the receiver's class instead.
+    let owner_name = if NIL_P(owner_name) {
+        cstr_to_string(rb_obj_classname(self_val)).unwrap_or_else(|| "Object".to_string())
+    } else {
+        rstring_lossy(owner_name)
+    };
+    format!("{}{}{}", owner_name, separator, name)
+}
+
+/// The name of the frame a call-type event (`CALL`, `C_CALL`, `B_CALL` or
+/// `CLASS`) opens.  Blocks are named after the method that defines them,
+/// which `method_id` reports (nil for top-level blocks).
+unsafe fn frame_name(arg: *mut rb_trace_arg_t, ev: rb_event_flag_t) -> String {
+    let self_val = rb_tracearg_self(arg);
+    if (ev & RUBY_EVENT_CLASS) != 0 {
+        return class_body_frame_name(self_val);
+    }
+    let mid_sym = if (ev & RUBY_EVENT_B_CALL) != 0 {
+        rb_tracearg_method_id(arg)
+    } else {
+        rb_tracearg_callee_id(arg)
+    };
+    let name = if NIL_P(mid_sym) {
+        "<top-level>".to_string()
+    } else {
+        let owner = method_owner(arg, self_val);
+        qualified_method_name(owner, self_val, rb_sym2id(mid_sym))
+    };
+    if (ev & RUBY_EVENT_B_CALL) != 0 {
+        format!("block in {}", name)
+    } else {
+        name
     }
-    let text = value_to_string_exception_safe(recorder, val);
-    let type_id = TraceWriter::ensure_type_id(tracer, TypeKind::Raw, &class_name);
-    encoder.write_raw(&text, type_id);
 }
 
-/// Encode a single Ruby value to CBOR bytes, resetting the encoder first.
-/// Returns a copy of the CBOR bytes suitable for passing to
-/// `register_variable_cbor` or `register_return_cbor`.
-unsafe fn encode_ruby_value_to_cbor(
-    recorder: &mut RecorderData,
-    tracer: &mut dyn TraceWriter,
-    encoder: &mut StreamingValueEncoder,
-    val: VALUE,
-) -> Vec<u8> {
-    encoder.reset();
-    encode_ruby_value_streaming(recorder, tracer, encoder, val, MAX_STREAMING_DEPTH);
-    encoder.get_bytes_copy()
+/// Where one thread is relative to the frame it was in when tracing
+/// started.
+#[derive(Default)]
+struct ThreadFrames {
+    /// Call-type events minus return-type events seen on the thread.
+    depth: i64,
+    /// The exception propagating out of the frame at `depth`, from its raise
+    /// until it is rescued or a frame returns normally.
+    unwinding: Option<Unwinding>,
 }
 
-/// Streaming variant of `record_variables`. Encodes Ruby local variables
-/// directly to CBOR bytes and registers them via `register_variable_cbor`,
-/// avoiding intermediate `ValueRecord` tree allocations.
-unsafe fn record_variables_streaming(
+/// An exception on its way out of a frame (see `track_unwinding`).
+struct Unwinding {
+    depth: i64,
+    exception: VALUE,
+    /// Whether the exception reached the frame from a callee rather than
+    /// being raised in it.
+    from_callee: bool,
+}
+
+/// Follow `ev` in the current thread's `ThreadFrames`.  Returns the
+/// exception a return event leaves its frame with, when the frame is the
+/// one that exception is propagating out of; the exception then moves on
+/// to the caller.  A rescue anywhere, or a return with a value or from
+/// another frame, ends the unwinding.
+///
+/// Exceptions rescued in C (`loop` stopping at `StopIteration`,
+/// `rb_rescue`) have no rescue event, and the VM clears `rb_errinfo` while
+/// hooks run, so a C function returning nil while an exception from one of
+/// its callees is pending is taken to have rescued it.  C functions that
+/// raised the exception themselves (`Integer#/`, `Hash#fetch`) do count as
+/// unwound.
+unsafe fn track_unwinding(
     recorder: &mut RecorderData,
-    tracer: &mut dyn TraceWriter,
-    encoder: &mut StreamingValueEncoder,
-    binding: VALUE,
-) {
-    let vars = rb_funcall(binding, recorder.id.local_variables, 0);
-    if !RB_TYPE_P(vars, rb_sys::ruby_value_type::RUBY_T_ARRAY) {
-        return;
+    arg: *mut rb_trace_arg_t,
+    ev: rb_event_flag_t,
+    thread_id: u64,
+) -> Option<VALUE> {
+    let frames = recorder.thread_frames.entry(thread_id).or_default();
+    let mut unwound = None;
+    if (ev & (RUBY_EVENT_CALL | RUBY_EVENT_C_CALL | RUBY_EVENT_B_CALL | RUBY_EVENT_CLASS)) != 0 {
+        frames.depth += 1;
+    } else if (ev
+        & (RUBY_EVENT_RETURN | RUBY_EVENT_C_RETURN | RUBY_EVENT_B_RETURN | RUBY_EVENT_END))
+        != 0
+    {
+        unwound = match &frames.unwinding {
+            Some(unwinding)
+                if unwinding.depth == frames.depth
+                    && ((ev & RUBY_EVENT_C_RETURN) == 0 || !unwinding.from_callee) =>
+            {
+                Some(unwinding.exception)
+            }
+            _ => None,
+        };
+        // Frames unwound by an exception report nil.
+        if (ev & RUBY_EVENT_END) == 0 && !NIL_P(rb_tracearg_return_value(arg)) {
+            unwound = None;
+        }
+        frames.depth -= 1;
+        frames.unwinding = unwound.map(|exception| Unwinding {
+            depth: frames.depth,
+            exception,
+            from_callee: true,
+        });
+    } else if (ev & RUBY_EVENT_RAISE) != 0 {
+        frames.unwinding = Some(Unwinding {
+            depth: frames.depth,
+            exception: rb_tracearg_raised_exception(arg),
+            from_callee: false,
+        });
+    } else if (ev & RUBY_EVENT_RESCUE) != 0 {
+        frames.unwinding = None;
     }
-    let len = RARRAY_LEN(vars) as usize;
-    let ptr = RARRAY_CONST_PTR(vars);
-    for i in 0..len {
-        let sym = *ptr.add(i);
-        let name = cstr_to_string(rb_id2name(rb_sym2id(sym))).unwrap_or_default();
-        let value = rb_funcall(binding, recorder.id.local_variable_get, 1, sym);
-        let cbor = encode_ruby_value_to_cbor(recorder, tracer, encoder, value);
-        TraceWriter::register_variable_cbor(tracer, &name, &cbor);
+    if frames.depth == 0 && frames.unwinding.is_none() {
+        recorder.thread_frames.remove(&thread_id);
     }
+    unwound
 }
 
-// Legacy record_variables has been removed — replaced by
-// record_variables_streaming (M59).
+/// Whether the `method:` rules let an event through.  Call-type events are
+/// matched by `frame_name` and push the decision for their frame onto the
+/// current thread's stack; returns pop it and the other events follow the
+/// thread's innermost frame.  Events outside any frame entered since
+/// tracing started are recorded.
+unsafe fn frame_event_traced(
+    recorder: &mut RecorderData,
+    arg: *mut rb_trace_arg_t,
+    ev: rb_event_flag_t,
+    thread_id: u64,
+) -> bool {
+    if (ev & (RUBY_EVENT_CALL | RUBY_EVENT_C_CALL | RUBY_EVENT_B_CALL | RUBY_EVENT_CLASS)) != 0 {
+        let traced = recorder.options.filter.frame_traced(&frame_name(arg, ev));
+        recorder
+            .frame_filter
+            .entry(thread_id)
+            .or_default()
+            .push(traced);
+        traced
+    } else if (ev
+        & (RUBY_EVENT_RETURN | RUBY_EVENT_C_RETURN | RUBY_EVENT_B_RETURN | RUBY_EVENT_END))
+        != 0
+    {
+        let Some(stack) = recorder.frame_filter.get_mut(&thread_id) else {
+            return true;
+        };
+        let traced = stack.pop().unwrap_or(true);
+        if stack.is_empty() {
+            recorder.frame_filter.remove(&thread_id);
+        }
+        traced
+    } else {
+        recorder
+            .frame_filter
+            .get(&thread_id)
+            .and_then(|stack| stack.last().copied())
+            .unwrap_or(true)
+    }
+}
 
-/// Streaming variant of parameter collection. Encodes each parameter value
-/// directly to CBOR bytes using the streaming encoder, registers it via
-/// `register_variable_cbor`, and returns (name, variable_id) pairs for
-/// constructing `CallRecord.args`.
-unsafe fn collect_and_register_params_streaming(
+/// Function name recorded for a class / module body, following the labels
+/// Ruby itself uses in backtraces: `<class:Foo>`, `<module:Bar>` and
+/// `singleton class` for `class << obj` bodies.
+unsafe fn class_body_frame_name(klass: VALUE) -> String {
+    let name = rb_mod_name(klass);
+    if NIL_P(name) {
+        return "singleton class".to_string();
+    }
+    let kind = if RB_TYPE_P(klass, rb_sys::ruby_value_type::RUBY_T_MODULE) {
+        "module"
+    } else {
+        "class"
+    };
+    format!("<{}:{}>", kind, rstring_lossy(name))
+}
+
+/// Record the return value of the innermost call of thread `thread_id`
+/// and close it.  Shared by Ruby methods (`RUBY_EVENT_RETURN`), C methods
+/// (`RUBY_EVENT_C_RETURN`) and blocks (`RUBY_EVENT_B_RETURN`).
+///
+/// Returns without a matching recorded call on the same thread are
+/// dropped (see `RecorderData::open_frames`).  `unwound` is the exception
+/// the frame is being left by (see `track_unwinding`), attached to the
+/// return step as `<exception>`; the VM reports nil as the value.
+#[allow(clippy::too_many_arguments)]
+unsafe fn record_return_streaming(
     recorder: &mut RecorderData,
     tracer: &mut dyn TraceWriter,
     encoder: &mut StreamingValueEncoder,
-    binding: VALUE,
-    defined_class: VALUE,
-    mid: ID,
-) -> Vec<FullValueRecord> {
-    let method_sym = rb_id2sym(mid);
-    if rb_method_boundp(defined_class, mid, 0) == 0 {
-        return Vec::new();
+    thread_id: u64,
+    path: &str,
+    line: i64,
+    ret: VALUE,
+    unwound: Option<VALUE>,
+) {
+    let Some(frames) = recorder.open_frames.get_mut(&thread_id) else {
+        // The thread is leaving a frame entered before tracing started and
+        // goes on in its caller, whose locals have not been registered.
+        if let Some(shadows) = recorder.frame_locals.get_mut(&thread_id) {
+            shadows.clear();
+        }
+        return;
+    };
+    *frames -= 1;
+    let depth = *frames;
+    if depth == 0 {
+        recorder.open_frames.remove(&thread_id);
     }
-    let method_obj = rb_funcall(defined_class, recorder.id.instance_method, 1, method_sym);
-    let params_ary = rb_funcall(method_obj, recorder.id.parameters, 0);
-    if !RB_TYPE_P(params_ary, rb_sys::ruby_value_type::RUBY_T_ARRAY) {
-        return Vec::new();
+    if let Some(shadows) = recorder.frame_locals.get_mut(&thread_id) {
+        shadows.truncate(depth + 1);
     }
-    let params_len = RARRAY_LEN(params_ary) as usize;
-    let params_ptr = RARRAY_CONST_PTR(params_ary);
-    let mut result = Vec::with_capacity(params_len);
-    for i in 0..params_len {
-        let pair = *params_ptr.add(i);
-        if !RB_TYPE_P(pair, rb_sys::ruby_value_type::RUBY_T_ARRAY) || RARRAY_LEN(pair) < 2 {
-            continue;
-        }
-        let pair_ptr = RARRAY_CONST_PTR(pair);
-        let name_sym = *pair_ptr.add(1);
-        if NIL_P(name_sym) {
-            continue;
-        }
-        if let Some(name) = cstr_to_string(rb_id2name(rb_sym2id(name_sym))) {
-            let value = rb_funcall(binding, recorder.id.local_variable_get, 1, name_sym);
-            let cbor = encode_ruby_value_to_cbor(recorder, tracer, encoder, value);
-            TraceWriter::register_variable_cbor(tracer, &name, &cbor);
-            // Stage the same CBOR bytes on the writer's pending-call-args
-            // buffer so the next `register_call` attaches them to the
-            // call record's `args` field.  Without this the CTFS call
-            // record has empty `args` and the frontend's calltrace pane
-            // renders calls as `f()` instead of `f(name=value)`.
-            TraceWriter::register_call_arg(tracer, &name, &cbor);
-            let var_id = TraceWriter::ensure_variable_id(tracer, &name);
-            // We still need a ValueRecord for FullValueRecord in CallRecord.args.
-            // Use a lightweight None sentinel — the CBOR data is already registered
-            // and the reader will use CBOR for the actual value.
-            result.push(FullValueRecord {
-                variable_id: var_id,
-                value: ValueRecord::None {
-                    type_id: recorder.error_type_id,
-                },
-            });
-        }
+    TraceWriter::register_step(tracer, Path::new(path), Line(line));
+    let cbor = encode_ruby_value_to_cbor(recorder, tracer, encoder, ret);
+    TraceWriter::register_variable_cbor(tracer, "<return_value>", &cbor);
+    if let Some(exc) = unwound {
+        record_exception_streaming(recorder, tracer, encoder, exc);
     }
-    result
+    TraceWriter::register_return_cbor(tracer, &cbor);
 }
 
-// Legacy collect_parameter_values / register_parameter_values have been
-// removed — replaced by collect_and_register_params_streaming (M59).
+/// Count a frame opened with `register_call` on thread `thread_id`.
+fn push_open_frame(recorder: &mut RecorderData, thread_id: u64) {
+    *recorder.open_frames.entry(thread_id).or_default() += 1;
+}
 
 unsafe fn record_event(tracer: &mut dyn TraceWriter, path: &str, line: i64, content: String) {
     TraceWriter::register_step(tracer, Path::new(path), Line(line));
     TraceWriter::register_special_event(tracer, EventLogKind::Write, "", &content)
 }
 
-unsafe extern "C" fn initialize(self_val: VALUE, out_dir: VALUE, format: VALUE) -> VALUE {
+/// `CodeTracerNativeRecorder.new(out_dir, format, options = nil)`.
+///
+/// Registered with arity -1 so the options hash stays optional for
+/// callers written against the two-argument form.
+unsafe extern "C" fn initialize(argc: c_int, argv: *const VALUE, self_val: VALUE) -> VALUE {
+    if !(2..=3).contains(&argc) {
+        rb_raise(
+            rb_eArgError,
+            c"wrong number of arguments (given %d, expected 2..3)".as_ptr() as *const c_char,
+            argc,
+        );
+    }
+    let out_dir = *argv.add(0);
+    let format = *argv.add(1);
+    let options = if argc > 2 { *argv.add(2) } else { Qnil.into() };
+
     let recorder_ptr = get_recorder(self_val);
     let recorder = &mut *recorder_ptr;
+    recorder.data.options = RecorderOptions::from_hash(options);
+    recorder.data.path_values = rb_ary_new();
+    recorder.data.traced_threads = rb_ary_new();
+    rb_ivar_set(
+        self_val,
+        rb_intern!("traced_threads"),
+        recorder.data.traced_threads,
+    );
+    rb_ivar_set(
+        self_val,
+        rb_intern!("path_values"),
+        recorder.data.path_values,
+    );
 
     // CTFS-only per `Recorder-CLI-Conventions.md` §4.  The second
     // positional argument is preserved for backward FFI compatibility
-    // (Ruby's `rb_define_method` registered this method with arity 2)
+    // (every Ruby caller passes it ahead of the optional options hash)
     // but only `:ctfs` / `:ct` are accepted.  Any other format symbol
     // raises a clear error so callers cannot silently ask for JSON or
     // binary and believe they got it.  Use `ct print` (shipped with
@@ -798,6 +3313,7 @@ unsafe extern "C" fn initialize(self_val: VALUE, out_dir: VALUE, format: VALUE)
                     // an in-memory event buffer.  register_call is the
                     // canonical FFI hook that emits the Call record.
                     TraceWriter::register_call(&mut **locked_tracer, func_id, vec![]);
+                    recorder.data.top_level_open = true;
                 }
                 Err(e) => {
                     let msg = std::ffi::CString::new(e.to_string())
@@ -828,6 +3344,11 @@ unsafe extern "C" fn flush_trace(self_val: VALUE) -> VALUE {
     let recorder_ptr = get_recorder(self_val);
     let recorder = &mut *recorder_ptr;
     let mut locked_tracer = recorder.tracer.lock().unwrap();
+    write_thread_events(
+        &recorder.thread_events,
+        &mut recorder.data,
+        &mut **locked_tracer,
+    );
 
     if let Err(e) = flush_to_dir(&mut **locked_tracer) {
         let msg = std::ffi::CString::new(e.to_string())
@@ -860,6 +3381,46 @@ unsafe extern "C" fn flush_trace(self_val: VALUE) -> VALUE {
     Qnil.into()
 }
 
+/// `register_serializer(klass, serializer)`: record instances of `klass`
+/// and its descendants as the value `serializer` turns them into.  A
+/// Symbol names a method to call on the instance; anything else is called
+/// with the instance (`serializer.call(value)`).  `nil` disables an
+/// inherited serializer.  Not consulted under `pure_capture`.
+unsafe extern "C" fn register_serializer(
+    self_val: VALUE,
+    klass: VALUE,
+    serializer: VALUE,
+) -> VALUE {
+    if !RB_TYPE_P(klass, rb_sys::ruby_value_type::RUBY_T_CLASS)
+        && !RB_TYPE_P(klass, rb_sys::ruby_value_type::RUBY_T_MODULE)
+    {
+        rb_raise(
+            rb_eArgError,
+            c"serializers are registered for a Class or Module".as_ptr() as *const c_char,
+        );
+    }
+    let recorder = &mut *get_recorder(self_val);
+    if NIL_P(recorder.data.serializers) {
+        // Both hashes hang off the recorder object (as hidden ivars) so
+        // the GC keeps them and the serializers alive.
+        recorder.data.serializers = rb_ident_hash_new();
+        recorder.data.serializer_cache = rb_ident_hash_new();
+        rb_ivar_set(
+            self_val,
+            rb_intern!("serializers"),
+            recorder.data.serializers,
+        );
+        rb_ivar_set(
+            self_val,
+            rb_intern!("serializer_cache"),
+            recorder.data.serializer_cache,
+        );
+    }
+    rb_hash_aset(recorder.data.serializers, klass, serializer);
+    rb_hash_clear(recorder.data.serializer_cache);
+    Qnil.into()
+}
+
 unsafe extern "C" fn record_event_api(
     self_val: VALUE,
     path: VALUE,
@@ -867,7 +3428,7 @@ unsafe extern "C" fn record_event_api(
     content: VALUE,
 ) -> VALUE {
     let recorder = &mut *get_recorder(self_val);
-    if recorder.data.in_event_hook {
+    if recorder.data.in_event_hook || !recording_current_thread(&recorder.data) {
         return Qnil.into();
     }
     let mut locked_tracer = recorder.tracer.lock().unwrap();
@@ -901,20 +3462,38 @@ unsafe extern "C" fn event_hook_raw(data: VALUE, arg: *mut rb_trace_arg_t) {
     recorder.data.in_event_hook = true;
 
     let mut locked_tracer = recorder.tracer.lock().unwrap();
+    write_thread_events(
+        &recorder.thread_events,
+        &mut recorder.data,
+        &mut **locked_tracer,
+    );
 
     let ev: rb_event_flag_t = rb_tracearg_event_flag(arg);
+    let thread = rb_thread_current();
+    let thread_id = recorder.threads.lock().unwrap().number(thread);
+    let unwound = if recorder.data.options.exceptions {
+        track_unwinding(&mut recorder.data, arg, ev, thread_id)
+    } else {
+        None
+    };
+    if (ev & recorder.data.options.recorded_events()) == 0 {
+        recorder.data.in_event_hook = false;
+        return;
+    }
     let path_val = rb_tracearg_path(arg);
     let line_val = rb_tracearg_lineno(arg);
-    let path = rstring_checked_or_empty(path_val);
+    let Some(path) = source_path(&mut recorder.data, path_val) else {
+        recorder.data.in_event_hook = false;
+        return;
+    };
+    let path: &str = &path;
     let line = rb_num2long(line_val) as i64;
-    if should_ignore_path(&path) {
+    if recorder.data.options.filter.frame_rules
+        && !frame_event_traced(&mut recorder.data, arg, ev, thread_id)
+    {
         recorder.data.in_event_hook = false;
         return;
     }
-
-    let thread_id: u64 = rb_eval_string(c"Thread.current".as_ptr() as *const c_char)
-        .try_into()
-        .unwrap();
     let thread_changed = if let Some(last_thread_id) = recorder.data.last_thread_id {
         last_thread_id != thread_id
     } else {
@@ -931,6 +3510,7 @@ unsafe extern "C" fn event_hook_raw(data: VALUE, arg: *mut rb_trace_arg_t) {
         // for the round-trip verification.
         TraceWriter::register_thread_switch(&mut **locked_tracer, thread_id);
         recorder.data.last_thread_id = Some(thread_id);
+        record_thread_name(&mut recorder.data, &mut **locked_tracer, thread, thread_id);
     }
 
     // Borrow the streaming encoder alongside the tracer. The encoder lives
@@ -939,9 +3519,15 @@ unsafe extern "C" fn event_hook_raw(data: VALUE, arg: *mut rb_trace_arg_t) {
 
     if (ev & RUBY_EVENT_LINE) != 0 {
         let binding = rb_tracearg_binding(arg);
-        TraceWriter::register_step(&mut **locked_tracer, Path::new(&path), Line(line));
+        TraceWriter::register_step(&mut **locked_tracer, Path::new(path), Line(line));
         if !NIL_P(binding) {
-            record_variables_streaming(&mut recorder.data, &mut **locked_tracer, encoder, binding);
+            record_variables_streaming(
+                &mut recorder.data,
+                &mut **locked_tracer,
+                encoder,
+                thread_id,
+                binding,
+            );
         }
     } else if (ev & RUBY_EVENT_CALL) != 0 {
         let binding = rb_tracearg_binding(arg);
@@ -949,79 +3535,212 @@ unsafe extern "C" fn event_hook_raw(data: VALUE, arg: *mut rb_trace_arg_t) {
         let self_val = rb_tracearg_self(arg);
         let mid_sym = rb_tracearg_callee_id(arg);
         let mid = rb_sym2id(mid_sym);
-        let defined_class = rb_funcall(self_val, recorder.data.id.class, 0);
+        let owner = method_owner(arg, self_val);
 
+        // The parameters of the running frame's own code: looking the
+        // method up by name finds a prepended module's override when
+        // this frame was entered through `super`.
         let param_args = if NIL_P(binding) {
             Vec::new()
         } else {
-            collect_and_register_params_streaming(
+            let params_ary = rb_tracearg_parameters(arg);
+            register_params_streaming(
                 &mut recorder.data,
                 &mut **locked_tracer,
                 encoder,
                 binding,
-                defined_class,
-                mid,
+                params_ary,
             )
         };
 
-        // Encode `self` via streaming encoder.
-        let class_name =
-            cstr_to_string(rb_obj_classname(self_val)).unwrap_or_else(|| "Object".to_string());
-        let text = value_to_string_exception_safe(&recorder.data, self_val);
-        let self_type =
-            TraceWriter::ensure_type_id(&mut **locked_tracer, TypeKind::Raw, &class_name);
-        encoder.reset();
-        encoder.write_raw(&text, self_type);
-        let self_cbor = encoder.get_bytes_copy();
-        TraceWriter::register_variable_cbor(&mut **locked_tracer, "self", &self_cbor);
-        // Also stage `self` as the first call arg so the frontend's
-        // calltrace pane can render the receiver alongside the method
-        // name (matches the Ruby convention of method calls being
-        // dispatched on a receiver).
-        TraceWriter::register_call_arg(&mut **locked_tracer, "self", &self_cbor);
-
-        let self_var_id = TraceWriter::ensure_variable_id(&mut **locked_tracer, "self");
-        let self_arg = FullValueRecord {
-            variable_id: self_var_id,
-            value: ValueRecord::None {
-                type_id: recorder.data.error_type_id,
-            },
-        };
+        let self_arg = register_self_arg(
+            &mut recorder.data,
+            &mut **locked_tracer,
+            encoder,
+            self_val,
+            false,
+        );
         let mut args = vec![self_arg];
         if !param_args.is_empty() {
             args.extend(param_args);
         }
-        TraceWriter::register_step(&mut **locked_tracer, Path::new(&path), Line(line));
-        let mut name = cstr_to_string(rb_id2name(mid)).unwrap_or_default();
-        if class_name != "Object" {
-            name = format!("{}#{}", class_name, name);
-        }
+        TraceWriter::register_step(&mut **locked_tracer, Path::new(path), Line(line));
+        let name = qualified_method_name(owner, self_val, mid);
         let fid = TraceWriter::ensure_function_id(
             &mut **locked_tracer,
             &name,
-            Path::new(&path),
+            Path::new(path),
             Line(line),
         );
         // Emit the call via register_call (the NimTraceWriter handles args
-        // through preceding register_variable_cbor calls — see lines above
-        // for `self` and per-parameter registration).  add_event is a no-op
-        // for the CTFS multi-stream backend.
+        // through preceding register_variable_cbor calls — see
+        // `register_self_arg` and per-parameter registration).  add_event is
+        // a no-op for the CTFS multi-stream backend.
+        TraceWriter::register_call(&mut **locked_tracer, fid, args);
+        push_open_frame(&mut recorder.data, thread_id);
+    } else if (ev & RUBY_EVENT_C_CALL) != 0 {
+        // C-implemented methods have no Ruby binding and the VM does not
+        // expose their argument values to event hooks, so the receiver
+        // (named by its class) is the only argument we can stage.  The
+        // step is taken at the call site (the path/line the VM reports for
+        // a cfunc frame is the caller's).
+        let self_val = rb_tracearg_self(arg);
+        let mid = rb_sym2id(rb_tracearg_callee_id(arg));
+        let owner = method_owner(arg, self_val);
+        let self_arg = register_self_arg(
+            &mut recorder.data,
+            &mut **locked_tracer,
+            encoder,
+            self_val,
+            true,
+        );
+        TraceWriter::register_step(&mut **locked_tracer, Path::new(path), Line(line));
+        let name = qualified_method_name(owner, self_val, mid);
+        let fid = TraceWriter::ensure_function_id(
+            &mut **locked_tracer,
+            &name,
+            Path::new(path),
+            Line(line),
+        );
+        TraceWriter::register_call(&mut **locked_tracer, fid, vec![self_arg]);
+        push_open_frame(&mut recorder.data, thread_id);
+    } else if (ev & RUBY_EVENT_B_CALL) != 0 {
+        // Blocks share `self` with the method that defines them, so only
+        // the block parameters are staged as call args.
+        let binding = rb_tracearg_binding(arg);
+        let args = if NIL_P(binding) {
+            Vec::new()
+        } else {
+            let params_ary = rb_tracearg_parameters(arg);
+            register_params_streaming(
+                &mut recorder.data,
+                &mut **locked_tracer,
+                encoder,
+                binding,
+                params_ary,
+            )
+        };
+        TraceWriter::register_step(&mut **locked_tracer, Path::new(path), Line(line));
+        let name = frame_name(arg, ev);
+        let fid = TraceWriter::ensure_function_id(
+            &mut **locked_tracer,
+            &name,
+            Path::new(path),
+            Line(line),
+        );
         TraceWriter::register_call(&mut **locked_tracer, fid, args);
-    } else if (ev & RUBY_EVENT_RETURN) != 0 {
-        TraceWriter::register_step(&mut **locked_tracer, Path::new(&path), Line(line));
+        push_open_frame(&mut recorder.data, thread_id);
+    } else if (ev & RUBY_EVENT_CLASS) != 0 {
+        // `self` inside the body is the class / module being (re)opened;
+        // bodies take no arguments.
+        let self_val = rb_tracearg_self(arg);
+        TraceWriter::register_step(&mut **locked_tracer, Path::new(path), Line(line));
+        let name = class_body_frame_name(self_val);
+        let fid = TraceWriter::ensure_function_id(
+            &mut **locked_tracer,
+            &name,
+            Path::new(path),
+            Line(line),
+        );
+        TraceWriter::register_call(&mut **locked_tracer, fid, vec![]);
+        push_open_frame(&mut recorder.data, thread_id);
+    } else if (ev & RUBY_EVENT_END) != 0 {
+        // The VM does not expose the body's value on `end`, so the frame
+        // returns nil.
+        record_return_streaming(
+            &mut recorder.data,
+            &mut **locked_tracer,
+            encoder,
+            thread_id,
+            path,
+            line,
+            Qnil.into(),
+            unwound,
+        );
+    } else if (ev & (RUBY_EVENT_RETURN | RUBY_EVENT_C_RETURN | RUBY_EVENT_B_RETURN)) != 0 {
         let ret = rb_tracearg_return_value(arg);
-        let cbor =
-            encode_ruby_value_to_cbor(&mut recorder.data, &mut **locked_tracer, encoder, ret);
-        TraceWriter::register_variable_cbor(&mut **locked_tracer, "<return_value>", &cbor);
-        TraceWriter::register_return_cbor(&mut **locked_tracer, &cbor);
+        record_return_streaming(
+            &mut recorder.data,
+            &mut **locked_tracer,
+            encoder,
+            thread_id,
+            path,
+            line,
+            ret,
+            unwound,
+        );
     } else if (ev & RUBY_EVENT_RAISE) != 0 {
         let exc = rb_tracearg_raised_exception(arg);
-        let msg = value_to_string_exception_safe(&recorder.data, exc);
-        TraceWriter::register_special_event(&mut **locked_tracer, EventLogKind::Error, "", &msg);
+        let msg = exception_message(&recorder.data, exc);
+        if recorder.data.options.exceptions {
+            // Attach the structured exception to the raising step and tag
+            // the error event with the exception class.
+            record_exception_streaming(&mut recorder.data, &mut **locked_tracer, encoder, exc);
+            let class_name = cstr_to_string(rb_obj_classname(exc)).unwrap_or_default();
+            TraceWriter::register_special_event(
+                &mut **locked_tracer,
+                EventLogKind::Error,
+                &class_name,
+                &msg,
+            );
+        } else {
+            TraceWriter::register_special_event(
+                &mut **locked_tracer,
+                EventLogKind::Error,
+                "",
+                &msg,
+            );
+        }
+    } else if (ev & RUBY_EVENT_RESCUE) != 0 {
+        // `ensure` clauses have no event of their own: they show up as
+        // plain line steps inside the frame being unwound, before its
+        // return.
+        let exc = rb_tracearg_raised_exception(arg);
+        TraceWriter::register_step(&mut **locked_tracer, Path::new(path), Line(line));
+        record_exception_streaming(&mut recorder.data, &mut **locked_tracer, encoder, exc);
+        let class_name = cstr_to_string(rb_obj_classname(exc)).unwrap_or_default();
+        let msg = exception_message(&recorder.data, exc);
+        TraceWriter::register_special_event(
+            &mut **locked_tracer,
+            EventLogKind::TraceLogEvent,
+            &class_name,
+            &format!("rescued {}: {}", class_name, msg),
+        );
     }
     recorder.data.in_event_hook = false;
 }
 
+/// Announce the name of `thread` (recorded as `thread_id`) with a
+/// `Thread` log event when it has one that was not announced yet.  The
+/// main thread is left unnamed so single-threaded traces do not change.
+unsafe fn record_thread_name(
+    recorder: &mut RecorderData,
+    tracer: &mut dyn TraceWriter,
+    thread: VALUE,
+    thread_id: u64,
+) {
+    if thread == rb_thread_main() {
+        return;
+    }
+    // `Thread#name` can be overridden; an error or a non-String leaves the
+    // thread unnamed.
+    let Some(name) = funcall_exception_safe(thread, recorder.id.name).and_then(|n| string_value(n))
+    else {
+        return;
+    };
+    let name = rstring_lossy(name);
+    if recorder.thread_names.get(&thread_id) == Some(&name) {
+        return;
+    }
+    TraceWriter::register_special_event(
+        tracer,
+        EventLogKind::TraceLogEvent,
+        "Thread",
+        &format!("thread {}: {}", thread_id, name),
+    );
+    recorder.thread_names.insert(thread_id, name);
+}
+
 unsafe extern "C" fn ex_callback(
     event: rb_event_flag_t,
     event_data: *const rb_internal_thread_event_data_t,
@@ -1033,39 +3752,146 @@ unsafe extern "C" fn ex_callback(
             // dedicated `register_thread_*` entry points in the Nim multi-
             // stream backend, this event was silently dropped.
             let recorder = user_data as *mut Recorder;
-            let mut locked_tracer = (*recorder).tracer.lock().unwrap();
-            TraceWriter::register_thread_start(&mut **locked_tracer, (*event_data).thread);
+            let thread_id = (*recorder)
+                .threads
+                .lock()
+                .unwrap()
+                .number((*event_data).thread);
+            (*recorder)
+                .thread_events
+                .lock()
+                .unwrap()
+                .push(ThreadEvent::Started(thread_id));
         }
         RUBY_INTERNAL_THREAD_EVENT_EXITED => {
             let recorder = user_data as *mut Recorder;
-            let mut locked_tracer = (*recorder).tracer.lock().unwrap();
-            TraceWriter::register_thread_exit(&mut **locked_tracer, (*event_data).thread);
-        }
-        /*RUBY_INTERNAL_THREAD_EVENT_READY => {
-            println!("RUBY_INTERNAL_THREAD_EVENT_READY");
-        }
-        RUBY_INTERNAL_THREAD_EVENT_RESUMED => {
-            println!("RUBY_INTERNAL_THREAD_EVENT_RESUMED");
+            let released = (*recorder)
+                .threads
+                .lock()
+                .unwrap()
+                .release((*event_data).thread);
+            if let Some(thread_id) = released {
+                (*recorder)
+                    .thread_events
+                    .lock()
+                    .unwrap()
+                    .push(ThreadEvent::Exited(thread_id));
+            }
         }
-        RUBY_INTERNAL_THREAD_EVENT_SUSPENDED => {
-            println!("RUBY_INTERNAL_THREAD_EVENT_SUSPENDED");
-        }*/
         _ => {}
     }
 }
 
 unsafe fn thread_register_callback(recorder: *mut Recorder) {
-    let q = rb_internal_thread_add_event_hook(
+    rb_internal_thread_add_event_hook(
         Some(ex_callback),
-        RUBY_INTERNAL_THREAD_EVENT_STARTED
-            | RUBY_INTERNAL_THREAD_EVENT_READY
-            | RUBY_INTERNAL_THREAD_EVENT_RESUMED
-            | RUBY_INTERNAL_THREAD_EVENT_SUSPENDED
-            | RUBY_INTERNAL_THREAD_EVENT_EXITED,
+        RUBY_INTERNAL_THREAD_EVENT_STARTED | RUBY_INTERNAL_THREAD_EVENT_EXITED,
         recorder as *mut c_void,
     );
 }
 
+#[cfg(test)]
+mod thread_number_tests {
+    use super::ThreadNumbers;
+    use rb_sys::VALUE;
+
+    #[test]
+    fn numbers_threads_in_order_and_releases_known_ones_only() {
+        let (main, worker, stranger) = (0x10 as VALUE, 0x20 as VALUE, 0x30 as VALUE);
+        let mut threads = ThreadNumbers::default();
+        assert_eq!(threads.number(main), 1);
+        assert_eq!(threads.number(worker), 2);
+        assert_eq!(threads.number(main), 1);
+        assert_eq!(threads.release(stranger), None);
+        assert_eq!(threads.release(worker), Some(2));
+        assert_eq!(threads.release(worker), None);
+        assert_eq!(threads.number(worker), 3);
+    }
+}
+
+#[cfg(test)]
+mod path_cache_tests {
+    use super::{cacheable_path, PathCache, MAX_CACHED_PATHS};
+    use rb_sys::VALUE;
+    use std::rc::Rc;
+
+    #[test]
+    fn caches_source_files_but_not_eval_code() {
+        assert!(cacheable_path("/app/lib/user.rb"));
+        assert!(cacheable_path("<internal:kernel>"));
+        assert!(!cacheable_path("(eval at /app/lib/user.rb:12)"));
+        assert!(!cacheable_path("(eval)"));
+        assert!(!cacheable_path("(irb)"));
+    }
+
+    #[test]
+    fn remembers_filtered_and_ignored_paths() {
+        let (user, vendored) = (0x10 as VALUE, 0x20 as VALUE);
+        let mut paths = PathCache::default();
+        assert_eq!(paths.get(user), None);
+        assert!(!paths.insert(user, Some(Rc::from("/app/lib/user.rb"))));
+        assert!(!paths.insert(vendored, None));
+        assert_eq!(paths.get(user), Some(&Some(Rc::from("/app/lib/user.rb"))));
+        assert_eq!(paths.get(vendored), Some(&None));
+    }
+
+    #[test]
+    fn starts_over_when_full() {
+        let mut paths = PathCache::default();
+        for key in 1..=MAX_CACHED_PATHS {
+            assert!(!paths.insert(key as VALUE, None));
+        }
+        assert_eq!(paths.get(1 as VALUE), Some(&None));
+        let overflow = (MAX_CACHED_PATHS + 1) as VALUE;
+        assert!(paths.insert(overflow, None));
+        assert_eq!(paths.get(1 as VALUE), None);
+        assert_eq!(paths.get(overflow), Some(&None));
+    }
+}
+
+#[cfg(test)]
+mod filter_rule_tests {
+    use super::{gem_name, glob_match, FilterRule, FilterRules};
+
+    #[test]
+    fn globs_keep_single_stars_within_a_segment() {
+        assert!(glob_match("*_spec.rb", "user_spec.rb"));
+        assert!(!glob_match("*.rb", "spec/user.rb"));
+        assert!(glob_match("spec/**", "spec/support/helpers.rb"));
+        assert!(glob_match("Foo::*#b?r", "Foo::Bar#bar"));
+    }
+
+    #[test]
+    fn gem_names_drop_the_version() {
+        let path = "/usr/lib/ruby/gems/3.3.0/gems/rack-session-2.0.0/lib/rack/session.rb";
+        assert_eq!(gem_name(path), Some("rack-session"));
+        assert_eq!(gem_name("/app/lib/user.rb"), None);
+    }
+
+    #[test]
+    fn include_rules_override_the_default_excludes() {
+        let mut rules = FilterRules::default();
+        let gem_file = "/usr/lib/ruby/gems/3.3.0/gems/rack-3.0.8/lib/rack.rb";
+        assert!(!rules.path_traced(gem_file));
+        assert!(!rules.path_traced("<internal:kernel>"));
+        assert!(rules.path_traced("/app/lib/user.rb"));
+
+        rules.include.push(FilterRule::parse("gem:rack"));
+        rules.exclude.push(FilterRule::parse("vendor/**"));
+        assert!(rules.path_traced(gem_file));
+        assert!(!rules.path_traced("/app/vendor/lib/thing.rb"));
+    }
+
+    #[test]
+    fn method_rules_match_frame_names() {
+        let mut rules = FilterRules::default();
+        rules.exclude.push(FilterRule::parse("method:Logger#*"));
+        assert!(!rules.frame_traced("Logger#info"));
+        assert!(rules.frame_traced("block in Logger#info"));
+        assert!(rules.frame_traced("User#save"));
+    }
+}
+
 #[no_mangle]
 pub extern "C" fn Init_codetracer_ruby_recorder() {
     unsafe {
@@ -1074,12 +3900,18 @@ pub extern "C" fn Init_codetracer_ruby_recorder() {
             rb_cObject,
         );
         rb_define_alloc_func(class, Some(ruby_recorder_alloc));
+        rb_define_singleton_method(
+            class,
+            c"ancestry_changed".as_ptr() as *const c_char,
+            Some(std::mem::transmute(ancestry_changed as *const ())),
+            0,
+        );
 
         rb_define_method(
             class,
             c"initialize".as_ptr() as *const c_char,
             Some(std::mem::transmute(initialize as *const ())),
-            2,
+            -1,
         );
         rb_define_method(
             class,
@@ -1093,6 +3925,24 @@ pub extern "C" fn Init_codetracer_ruby_recorder() {
             Some(std::mem::transmute(disable_tracing as *const ())),
             0,
         );
+        rb_define_method(
+            class,
+            c"disable_global_tracing".as_ptr() as *const c_char,
+            Some(std::mem::transmute(disable_global_tracing as *const ())),
+            0,
+        );
+        rb_define_method(
+            class,
+            c"enable_tracing_on_thread".as_ptr() as *const c_char,
+            Some(std::mem::transmute(enable_tracing_on_thread as *const ())),
+            1,
+        );
+        rb_define_method(
+            class,
+            c"disable_tracing_on_thread".as_ptr() as *const c_char,
+            Some(std::mem::transmute(disable_tracing_on_thread as *const ())),
+            1,
+        );
         rb_define_method(
             class,
             c"flush_trace".as_ptr() as *const c_char,
@@ -1105,5 +3955,11 @@ pub extern "C" fn Init_codetracer_ruby_recorder() {
             Some(std::mem::transmute(record_event_api as *const ())),
             3,
         );
+        rb_define_method(
+            class,
+            c"register_serializer".as_ptr() as *const c_char,
+            Some(std::mem::transmute(register_serializer as *const ())),
+            2,
+        );
     }
 }
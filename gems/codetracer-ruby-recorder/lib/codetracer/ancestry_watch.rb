@@ -0,0 +1,30 @@
+# SPDX-License-Identifier: MIT
+
+module CodeTracer
+  # Tells the native recorder whenever a module is included, prepended or
+  # extended anywhere, so the serializers it resolved per class are looked
+  # up again.  Installed with the first registered serializer.
+  module AncestryWatch
+    def self.install
+      Module.prepend(self) unless Module.ancestors.include?(self)
+    end
+
+    def append_features(base)
+      result = super
+      CodeTracerNativeRecorder.ancestry_changed
+      result
+    end
+
+    def prepend_features(base)
+      result = super
+      CodeTracerNativeRecorder.ancestry_changed
+      result
+    end
+
+    def extend_object(obj)
+      result = super
+      CodeTracerNativeRecorder.ancestry_changed
+      result
+    end
+  end
+end
@@ -12,14 +12,21 @@
 # * `CODETRACER_RUBY_RECORDER_OUT_DIR` — fallback for `--out-dir`.
 # * `CODETRACER_RUBY_RECORDER_DISABLED` — set to `1` or `true` to skip
 #   recording entirely (the target script still runs).
+# * `CODETRACER_RUBY_RECORDER_INCLUDE` / `CODETRACER_RUBY_RECORDER_EXCLUDE` —
+#   comma-separated include / exclude rules (see RubyRecorder#initialize).
 
 require 'optparse'
 require 'fileutils'
 require 'rbconfig'
 require_relative 'codetracer/kernel_patches'
+require_relative 'codetracer/ancestry_watch'
 
 module CodeTracer
   class RubyRecorder
+    # Project config file, read from the working directory, whose `include`
+    # and `exclude` lists are added to the rules passed in code.
+    CONFIG_FILE = '.codetracer-ruby-recorder.yml'
+
     # Parse the disabled environment variable, accepting `1` / `true`
     # (case-insensitive) as truthy.  Any other value (including unset)
     # leaves recording enabled.  Convention §5.
@@ -30,6 +37,25 @@ module CodeTracer
       raw.strip.downcase == '1' || raw.strip.downcase == 'true'
     end
 
+    # Merge the include / exclude rules from CONFIG_FILE and the
+    # environment into +options+.  Rules from all sources apply together.
+    def self.with_filter_rules(options)
+      config = {}
+      if File.exist?(CONFIG_FILE)
+        require 'yaml'
+        config = YAML.safe_load(File.read(CONFIG_FILE)) || {}
+        unless config.is_a?(Hash)
+          raise ArgumentError, "#{CONFIG_FILE}: expected a mapping with `include` / `exclude` lists, " \
+                               "got #{config.class}"
+        end
+      end
+      %i[include exclude].each_with_object(options.dup) do |key, merged|
+        env = ENV["CODETRACER_RUBY_RECORDER_#{key.upcase}"].to_s.split(',').map(&:strip)
+        rules = Array(config[key.to_s]).map(&:to_s) + env.reject(&:empty?) + Array(options[key])
+        merged[key] = rules unless rules.empty?
+      end
+    end
+
     def self.parse_argv_and_trace_ruby_file(argv)
       options = {}
       parser = OptionParser.new do |opts|
@@ -41,6 +67,60 @@ module CodeTracer
                 '(defaults to ./ct-traces, or $CODETRACER_RUBY_RECORDER_OUT_DIR when set).') do |dir|
           options[:out_dir] = dir
         end
+        opts.on('--c-calls',
+                'Also record calls into C-implemented methods (Array#map, Integer#times, ...).') do
+          options[:c_calls] = true
+        end
+        opts.on('--blocks',
+                'Record each block / lambda invocation as its own frame (e.g. "block in Foo#bar").') do
+          options[:blocks] = true
+        end
+        opts.on('--class-bodies',
+                'Record class / module bodies as their own frames (e.g. "<class:Foo>").') do
+          options[:class_bodies] = true
+        end
+        opts.on('--exceptions',
+                'Record raised exceptions structurally and where they are rescued.') do
+          options[:exceptions] = true
+        end
+        opts.on('--object-ids',
+                'Tag recorded objects with their object_id so aliasing is visible.') do
+          options[:object_ids] = true
+        end
+        opts.on('--pure-capture',
+                'Read recorded objects through C APIs only, never calling their methods.') do
+          options[:pure_capture] = true
+        end
+        opts.on('--structured-self',
+                'Record the receiver of each call with its state instead of its to_s.') do
+          options[:structured_self] = true
+        end
+        opts.on('--incremental-locals',
+                'Record a local variable only at the steps where its value changes.') do
+          options[:incremental_locals] = true
+        end
+        opts.on('--include PATTERN',
+                'Record files, gems (gem:NAME) or methods (method:Class#name) matching PATTERN',
+                'even if excluded.  Repeatable.') do |pattern|
+          (options[:include] ||= []) << pattern
+        end
+        opts.on('--exclude PATTERN',
+                'Do not record files, gems (gem:NAME) or methods (method:Class#name)',
+                'matching PATTERN.  Repeatable.') do |pattern|
+          (options[:exclude] ||= []) << pattern
+        end
+        opts.on('--max-depth N', Integer,
+                'Nesting levels recorded per value (1-32, default 10).') do |n|
+          options[:max_depth] = n
+        end
+        opts.on('--max-elements N', Integer,
+                'Elements recorded per Array, Hash or Set (default: all).') do |n|
+          options[:max_elements] = n
+        end
+        opts.on('--max-string-bytes N', Integer,
+                'Bytes recorded per String (default: all).') do |n|
+          options[:max_string_bytes] = n
+        end
         opts.on('-h', '--help', 'Print this help and exit') do
           puts opts
           puts ''
@@ -56,6 +136,8 @@ module CodeTracer
           puts '                                      (overridden by --out-dir).'
           puts '  CODETRACER_RUBY_RECORDER_DISABLED  Set to 1 or true to skip recording'
           puts '                                      entirely; the script still runs.'
+          puts '  CODETRACER_RUBY_RECORDER_INCLUDE   Comma-separated --include rules.'
+          puts '  CODETRACER_RUBY_RECORDER_EXCLUDE   Comma-separated --exclude rules.'
           puts '  CODETRACER_RUBY_RECORDER_DEBUG     Enable additional debug logging.'
           exit
         end
@@ -106,15 +188,20 @@ module CodeTracer
         return 0
       end
 
-      trace_ruby_file(program, out_dir, program_args)
+      recorder_options = options.slice(:c_calls, :blocks, :class_bodies, :exceptions, :object_ids,
+                                     :pure_capture, :structured_self, :incremental_locals,
+                                     :max_depth, :max_elements, :max_string_bytes,
+                                     :include, :exclude)
+      trace_ruby_file(program, out_dir, program_args, **recorder_options)
       0
     end
 
     # Trace the given Ruby program and write a CTFS bundle to `out_dir`.
     # The output format is hard-pinned to CTFS — see `Recorder-CLI-Conventions.md`
-    # §4 (CTFS-only).
-    def self.trace_ruby_file(program, out_dir, program_args = [])
-      recorder = RubyRecorder.new(out_dir)
+    # §4 (CTFS-only).  +options+ are forwarded to the native recorder
+    # (see RubyRecorder#initialize).
+    def self.trace_ruby_file(program, out_dir, program_args = [], **options)
+      recorder = RubyRecorder.new(out_dir, **options)
       return 1 unless recorder.available?
 
       ENV['CODETRACER_RUBY_RECORDER_OUT_DIR'] = out_dir
@@ -155,30 +242,146 @@ module CodeTracer
       parse_argv_and_trace_ruby_file(argv)
     end
 
-    def initialize(out_dir)
+    # Supported +options+:
+    #
+    # * `c_calls:` — also record calls into C-implemented methods
+    #   (`Array#map`, `Hash#each`, ...).  Off by default because it
+    #   multiplies the number of recorded calls.  Only the receiver's
+    #   class is recorded; the VM does not expose C call arguments.
+    # * `blocks:` — record every block / lambda invocation as its own
+    #   frame named `block in <method>`, with its block parameters as
+    #   call arguments and its result as the return value.
+    # * `class_bodies:` — record the code run inside `class Foo ... end`
+    #   and `module Bar ... end` as `<class:Foo>` / `<module:Bar>` frames
+    #   instead of attributing it to the enclosing frame.
+    # * `exceptions:` — attach each raised exception (class, message,
+    #   backtrace and `cause` chain) as `<exception>` to the raising step
+    #   and to every frame it unwinds, and record where it is rescued.
+    # * `object_ids:` — tag every recorded heap object with its
+    #   `object_id`, so variables that alias the same object can be
+    #   recognised, and write repeats within one value as back-references
+    #   to that `object_id`.  Reference cycles end in a back-reference with
+    #   or without it; without it, the back-reference holds the position
+    #   of the repeated object among the enclosing ones (0 for the root).
+    # * `pure_capture:` — read object state only through the interpreter's
+    #   C API (instance variables, struct members, hash entries) and never
+    #   call methods of recorded values (`to_s`, `members`, `cause`, ...),
+    #   so recording cannot change program behaviour.  Values that only
+    #   expose their state through methods (Set, BigDecimal, Regexp source,
+    #   exception backtraces) are recorded in less detail.
+    # * `structured_self:` — record `self` at each call like any other
+    #   value (instance variables, struct members, subject to the capture
+    #   limits below) rather than as its `to_s` text.  C function
+    #   receivers (`c_calls:`) are recorded by class name either way.
+    # * `incremental_locals:` — at line steps, record only the locals whose
+    #   value differs from the last one recorded in the same call; the
+    #   names left out are listed in an `<unchanged>` variable.
+    # * `max_depth:`, `max_elements:`, `max_string_bytes:` — capture limits:
+    #   nesting levels expanded per value (1-32, default 10; the encoder's
+    #   nesting limit may cut a value sooner), elements recorded per
+    #   Array / Hash / Set and bytes per String (default: no limit).  Cut
+    #   values are recorded as `<truncated>` with their original size;
+    #   objects past the depth limit as `<depth-limit>`.
+    # * `include:`, `exclude:` — Arrays of rules choosing what is recorded:
+    #   path globs (`vendor/**`, `spec/support/*.rb`), installed gems
+    #   (`gem:rack`) and frame names (`method:Logger#*`, `method:Admin::*`).
+    #   `*` stays within a path segment, `**` crosses them.  VM internals,
+    #   the standard library and installed gems are excluded by default;
+    #   anything an include rule matches is recorded even if excluded.
+    #   Rules from CONFIG_FILE and `CODETRACER_RUBY_RECORDER_INCLUDE` /
+    #   `_EXCLUDE` are added to these.
+    def initialize(out_dir, **options)
       @recorder = nil
       @active = false
-      load_native_recorder(out_dir)
+      @all_threads = false
+      @traced_threads = []
+      load_native_recorder(out_dir, self.class.with_filter_rules(options))
     end
 
-    # Start the recorder and install kernel patches
-    def start
-      return if @active || @recorder.nil?
+    # Start the recorder and install kernel patches.  By default every
+    # thread is recorded; +threads+ (a Thread, an Array of them or
+    # `:current`) limits recording to those threads, through hooks attached
+    # to each of them.  Calling it again adds threads.
+    def start(threads: nil)
+      return if @recorder.nil?
+
+      if threads.nil?
+        @recorder.enable_tracing
+        @all_threads = true
+      else
+        resolve_threads(threads).each do |thread|
+          @recorder.enable_tracing_on_thread(thread)
+          @traced_threads << thread unless @traced_threads.include?(thread)
+        end
+      end
+      return if @active
 
-      @recorder.enable_tracing
       CodeTracer::KernelPatches.install(self)
       @active = true
     end
 
-    # Stop the recorder and remove kernel patches
-    def stop
+    # Stop the recorder and remove kernel patches.  With +threads+ only
+    # those threads stop being recorded; the recorder stops once none is
+    # left.
+    def stop(threads: nil)
       return unless @active
 
+      if threads
+        resolve_threads(threads).each do |thread|
+          @recorder.disable_tracing_on_thread(thread)
+          @traced_threads.delete(thread)
+        end
+        return if @all_threads || @traced_threads.any?
+      end
       CodeTracer::KernelPatches.uninstall(self)
       @recorder.disable_tracing if @recorder
+      @all_threads = false
+      @traced_threads.clear
       @active = false
     end
 
+    # Record only while the block runs and return its value.  Only the
+    # calling thread is recorded unless +threads+ names others (see
+    # #start) or is `:all`; other threads, e.g. the rest of a server's
+    # workers, keep running untraced.  On exit only what this call enabled
+    # is undone, so nested blocks and blocks run while the recorder is
+    # already started leave the outer recording running.
+    #
+    #   recorder.record { handle(request) }
+    #   recorder.record(threads: :all) { run_batch }
+    def record(threads: :current)
+      if threads == :all
+        enabled = !@all_threads
+        start if enabled
+      else
+        enabled = resolve_threads(threads) - @traced_threads
+        start(threads: enabled) unless enabled.empty?
+      end
+      yield
+    ensure
+      case enabled
+      when true then stop_all_threads
+      when Array then stop(threads: enabled) unless enabled.empty?
+      end
+    end
+
+    # Record instances of +klass+ (and its subclasses) as a simpler value:
+    # the result of calling +method_name+ on them, or of the block.
+    #
+    #   recorder.register_serializer(Money, :to_s)
+    #   recorder.register_serializer(User) { |user| { id: user.id } }
+    #
+    # Passing neither disables a serializer inherited from an ancestor.
+    # Serializers are not used in `pure_capture:` mode.
+    def register_serializer(klass, method_name = nil, &block)
+      raise ArgumentError, 'pass a method name or a block, not both' if method_name && block
+
+      return unless @recorder
+
+      CodeTracer::AncestryWatch.install
+      @recorder.register_serializer(klass, method_name || block)
+    end
+
     # Record event for kernel patches integration
     def record_event(path, line, content)
       @recorder.record_event(path, line, content) if @recorder
@@ -196,7 +399,21 @@ module CodeTracer
 
     private
 
-    def load_native_recorder(out_dir)
+    def resolve_threads(threads)
+      threads == :current ? [Thread.current] : Array(threads)
+    end
+
+    # Undo `start` without threads: go back to the threads started
+    # individually, or stop when there are none.
+    def stop_all_threads
+      return unless @active && @all_threads
+      return stop if @traced_threads.empty?
+
+      @recorder.disable_global_tracing
+      @all_threads = false
+    end
+
+    def load_native_recorder(out_dir, options)
       begin
         # Load native extension at module level
         ext_dir = File.expand_path('../ext/native_tracer/target/release', __dir__)
@@ -221,7 +438,7 @@ module CodeTracer
         # but every Ruby caller passes :ctfs.  See
         # ext/native_tracer/src/lib.rs::begin_trace which now writes only
         # the CTFS multi-stream container.
-        @recorder = CodeTracerNativeRecorder.new(out_dir, :ctfs)
+        @recorder = CodeTracerNativeRecorder.new(out_dir, :ctfs, options)
       rescue Exception => e
         warn "native tracer unavailable: #{e}"
         @recorder = nil
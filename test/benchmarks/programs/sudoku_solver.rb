@@ -0,0 +1,19 @@
+# frozen_string_literal: true
+# The sudoku test program on a full puzzle: a deep backtracking recursion
+# with a few events per cell, so per-event overhead in the recorder (such
+# as looking up the current thread) dominates the recording time.
+
+require_relative '../../../test-programs/rb_sudoku_solver/sudoku_solver'
+
+board = [
+  [5, 3, 0, 0, 7, 0, 0, 0, 0],
+  [6, 0, 0, 1, 9, 5, 0, 0, 0],
+  [0, 9, 8, 0, 0, 0, 0, 6, 0],
+  [8, 0, 0, 0, 6, 0, 0, 0, 3],
+  [4, 0, 0, 8, 0, 3, 0, 0, 1],
+  [7, 0, 0, 0, 2, 0, 0, 0, 6],
+  [0, 6, 0, 0, 0, 0, 2, 8, 0],
+  [0, 0, 0, 4, 1, 9, 0, 0, 5],
+  [0, 0, 0, 0, 8, 0, 0, 7, 9]
+]
+SudokuSolver.new(board).solve
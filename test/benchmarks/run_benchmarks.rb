@@ -18,6 +18,7 @@ COLUMN_NAMES = {
   ruby: 'Ruby (no tracing)',
   json: 'JSON',
   capnp: 'CAPNP',
+  incremental: 'CAPNP (incremental locals)',
   pure: 'JSON (PureRuby)'
 }.freeze
 
@@ -71,8 +72,8 @@ end
 # Run a single benchmark by name
 def run_benchmark(name)
   program = File.join(PROGRAMS_DIR, "#{name}.rb")
-  fixture = File.join(FIXTURES_DIR, "#{name}_trace.json")
-  raise 'Reference trace unavailable' unless File.exist?(fixture)
+  # Reference traces are only needed for the strict comparison disabled
+  # above, so benchmarks without one (sudoku_solver) still run.
 
   base_dir = File.join(TMP_DIR, name)
   FileUtils.rm_rf(base_dir)
@@ -113,6 +114,22 @@ def run_benchmark(name)
   results[:native_bin_ok] = !native_bin_files.empty?
   results[:native_bin_bytes] = native_bin_files.sum { |p| File.size(p) }
 
+  # Same recorder with --incremental-locals: locals are only written at
+  # the steps where their value changes.  Compare against the lane above
+  # to see what skipping unchanged locals saves.
+  native_inc_dir = File.join(TMP_DIR, name, 'native_incremental')
+  FileUtils.mkdir_p(native_inc_dir)
+  elapsed = Benchmark.realtime do
+    system(RbConfig.ruby, File.expand_path('../../gems/codetracer-ruby-recorder/bin/codetracer-ruby-recorder', __dir__),
+           '--out-dir', native_inc_dir, '--incremental-locals', program)
+    raise 'Native incremental trace failed' unless $?.success?
+  end
+  results[:native_inc_ms] = (elapsed * 1000).round
+  native_inc_files = Dir.glob(File.join(native_inc_dir, '**', '*'))
+                       .select { |p| File.file?(p) }
+  results[:native_inc_ok] = !native_inc_files.empty?
+  results[:native_inc_bytes] = native_inc_files.sum { |p| File.size(p) }
+
   pure_dir = File.join(TMP_DIR, name, 'pure')
   FileUtils.mkdir_p(pure_dir)
   elapsed = Benchmark.realtime do
@@ -142,14 +159,16 @@ if options[:write_report] == 'console'
               *results.map { |r| "#{r[:native_ok] ? '✓' : '✗'} #{comma(r[:native_ms])}ms #{comma(r[:native_bytes])}B".length }].max + 2
   capnp_w  = [COLUMN_NAMES[:capnp].length,
               *results.map { |r| "#{comma(r[:native_bin_ms])}ms #{comma(r[:native_bin_bytes])}B".length }].max + 2
+  inc_w    = [COLUMN_NAMES[:incremental].length,
+              *results.map { |r| "#{comma(r[:native_inc_ms])}ms #{comma(r[:native_inc_bytes])}B".length }].max + 2
   pure_w   = [COLUMN_NAMES[:pure].length,
               *results.map { |r| "#{r[:pure_ok] ? '✓' : '✗'} #{comma(r[:pure_ms])}ms #{comma(r[:pure_bytes])}B".length }].max + 2
 
-  total_width = name_w + ruby_w + json_w + capnp_w + pure_w + 5
+  total_width = name_w + ruby_w + json_w + capnp_w + inc_w + pure_w + 6
 
   puts
   puts "=" * total_width
-  printf "| %-#{name_w-2}s | %#{ruby_w-2}s | %-#{json_w-2}s | %#{capnp_w-2}s | %-#{pure_w-2}s |\n", COLUMN_NAMES[:benchmark], COLUMN_NAMES[:ruby], COLUMN_NAMES[:json], COLUMN_NAMES[:capnp], COLUMN_NAMES[:pure]
+  printf "| %-#{name_w-2}s | %#{ruby_w-2}s | %-#{json_w-2}s | %#{capnp_w-2}s | %#{inc_w-2}s | %-#{pure_w-2}s |\n", COLUMN_NAMES[:benchmark], COLUMN_NAMES[:ruby], COLUMN_NAMES[:json], COLUMN_NAMES[:capnp], COLUMN_NAMES[:incremental], COLUMN_NAMES[:pure]
   puts "=" * total_width
 
   # Rows
@@ -157,19 +176,20 @@ if options[:write_report] == 'console'
     ruby_s   = "#{comma(r[:ruby_ms])}ms"
     json_s   = "#{r[:native_ok] ? '✓' : '✗'} #{comma(r[:native_ms])}ms #{comma(r[:native_bytes])}B"
     capnp_s  = "#{comma(r[:native_bin_ms])}ms #{comma(r[:native_bin_bytes])}B"
+    inc_s    = "#{comma(r[:native_inc_ms])}ms #{comma(r[:native_inc_bytes])}B"
     pure_s   = "#{r[:pure_ok] ? '✓' : '✗'} #{comma(r[:pure_ms])}ms #{comma(r[:pure_bytes])}B"
-    printf "| %-#{name_w-2}s | %#{ruby_w-2}s | %-#{json_w-2}s | %#{capnp_w-2}s | %-#{pure_w-2}s |\n", r[:name], ruby_s, json_s, capnp_s, pure_s
+    printf "| %-#{name_w-2}s | %#{ruby_w-2}s | %-#{json_w-2}s | %#{capnp_w-2}s | %#{inc_w-2}s | %-#{pure_w-2}s |\n", r[:name], ruby_s, json_s, capnp_s, inc_s, pure_s
   end
   puts "=" * total_width
   puts
 
   # Summary
-  passed = results.count { |r| r[:native_ok] && r[:pure_ok] && r[:native_bin_ok] }
+  passed = results.count { |r| r[:native_ok] && r[:pure_ok] && r[:native_bin_ok] && r[:native_inc_ok] }
   total = results.length
   puts "Results: #{passed}/#{total} benchmarks passed"
 
   # Exit with non-zero if any failed
-  exit 1 unless results.all? { |r| r[:native_ok] && r[:pure_ok] && r[:native_bin_ok] }
+  exit 1 unless results.all? { |r| r[:native_ok] && r[:pure_ok] && r[:native_bin_ok] && r[:native_inc_ok] }
 else
   dest = options[:write_report]
   FileUtils.mkdir_p(File.dirname(dest))
@@ -185,6 +205,9 @@ else
         native_ok: r[:native_ok],
         native_bin_ms: r[:native_bin_ms],
         native_bin_bytes: r[:native_bin_bytes],
+        native_inc_ms: r[:native_inc_ms],
+        native_inc_bytes: r[:native_inc_bytes],
+        native_inc_ok: r[:native_inc_ok],
         pure_ms: r[:pure_ms],
         pure_bytes: r[:pure_bytes],
         pure_ok: r[:pure_ok]
@@ -194,7 +217,7 @@ else
   when '.svg'
     row_height = 25
     height     = 40 + row_height * results.size
-    svg = +"<svg xmlns='http://www.w3.org/2000/svg' width='700' height='#{height}'>\n"
+    svg = +"<svg xmlns='http://www.w3.org/2000/svg' width='850' height='#{height}'>\n"
     svg << "  <foreignObject width='100%' height='100%'>\n"
     cell_style = "style='border:1px solid #999;padding:4px;text-align:center;'"
     svg << "    <table style='border-collapse:collapse;font-family:sans-serif;'>\n"
@@ -203,6 +226,7 @@ else
     svg << "<th #{cell_style}>#{COLUMN_NAMES[:ruby]}</th>"
     svg << "<th #{cell_style}>#{COLUMN_NAMES[:json]}</th>"
     svg << "<th #{cell_style}>#{COLUMN_NAMES[:capnp]}</th>"
+    svg << "<th #{cell_style}>#{COLUMN_NAMES[:incremental]}</th>"
     svg << "<th #{cell_style}>#{COLUMN_NAMES[:pure]}</th></tr></thead>\n"
     svg << "      <tbody>\n"
     results.each_with_index do |r, idx|
@@ -210,8 +234,9 @@ else
       ruby_s = "#{comma(r[:ruby_ms])}ms"
       json_s = "#{r[:native_ok] ? '✓' : '✗'} #{comma(r[:native_ms])}ms #{comma(r[:native_bytes])}B"
       capnp_s = "#{comma(r[:native_bin_ms])}ms #{comma(r[:native_bin_bytes])}B"
+      inc_s = "#{comma(r[:native_inc_ms])}ms #{comma(r[:native_inc_bytes])}B"
       pure_s = "#{r[:pure_ok] ? '✓' : '✗'} #{comma(r[:pure_ms])}ms #{comma(r[:pure_bytes])}B"
-      svg << "        <tr#{row_style}><td #{cell_style}>#{r[:name]}</td><td #{cell_style}>#{ruby_s}</td><td #{cell_style}>#{json_s}</td><td #{cell_style}>#{capnp_s}</td><td #{cell_style}>#{pure_s}</td></tr>\n"
+      svg << "        <tr#{row_style}><td #{cell_style}>#{r[:name]}</td><td #{cell_style}>#{ruby_s}</td><td #{cell_style}>#{json_s}</td><td #{cell_style}>#{capnp_s}</td><td #{cell_style}>#{inc_s}</td><td #{cell_style}>#{pure_s}</td></tr>\n"
     end
     svg << "      </tbody>\n"
     svg << "    </table>\n"
@@ -223,7 +248,7 @@ else
   end
 
   # Warn and exit if any failures
-  unless results.all? { |r| r[:native_ok] && r[:pure_ok] && r[:native_bin_ok] }
+  unless results.all? { |r| r[:native_ok] && r[:pure_ok] && r[:native_bin_ok] && r[:native_inc_ok] }
     warn 'One or more traces differ from reference!'
     exit 1
   end
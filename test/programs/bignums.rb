@@ -0,0 +1,22 @@
+# frozen_string_literal: true
+# Integers beyond the 64-bit range must be recorded losslessly:
+#   - positive and negative Bignums held in locals, arguments and returns
+#   - a Bignum that still fits in an i64 (2**62 is a Bignum on 64-bit MRI)
+
+def factorial(n)
+  (1..n).reduce(1, :*)
+end
+
+def negate(value)
+  -value
+end
+
+big = 2**100
+negative = negate(big)
+boundary = 2**62
+fact = factorial(25)
+
+puts big
+puts negative
+puts boundary
+puts fact
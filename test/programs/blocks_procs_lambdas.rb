@@ -7,8 +7,8 @@
 #   - arity strictness differs (lambda is strict, proc is permissive)
 #
 # RECORDER BUG: the pure and native recorders disagree on how a Proc
-# value is serialised (pure → Struct{}, native → Raw with `to_s` text
-# including the object's memory address).  The native semantic-match
+# value is serialised (pure → Struct{}, native → a `Proc` struct of its
+# source location, lambda-ness and arity).  The native semantic-match
 # half of `test_blocks_procs_lambdas` is therefore skipped — see the
 # `NATIVE_SEMANTIC_SKIP` table in `test/test_tracer.rb`.  The pure
 # recorder still asserts the full strict snapshot, so any change in
@@ -0,0 +1,30 @@
+# frozen_string_literal: true
+# Locals holding callables and classes / modules.
+
+module Greeting
+  def greet(name)
+    "hello #{name}"
+  end
+end
+
+class Greeter
+  include Greeting
+end
+
+doubler = ->(x) { x * 2 }
+counter = proc { |a, b| [a, b] }
+bound = Greeter.new.method(:greet)
+unbound = Greeter.instance_method(:greet)
+klass = Greeter
+mod = Greeting
+broken = Greeter.new.method(:greet)
+def broken.owner = raise('no owner')
+def broken.source_location = raise('no location')
+
+puts doubler.call(21)
+puts counter.arity
+puts bound.call('world')
+puts unbound.owner
+puts klass.ancestors.first
+puts mod.name
+puts broken.call('again')
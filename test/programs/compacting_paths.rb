@@ -0,0 +1,22 @@
+# frozen_string_literal: true
+# Paths the recorder caches must survive GC compaction, and code
+# evaluated in a loop (a fresh "(eval at ...)" path per call) must keep
+# being recorded without being cached.
+
+def file_work(n)
+  n + 1
+end
+
+3.times do |i|
+  eval("def eval_work_#{i}(n)\n  n * 2\nend\neval_work_#{i}(#{i})")
+  eval('file_work(1)')
+end
+
+begin
+  GC.compact
+rescue NotImplementedError
+  nil
+end
+junk = Array.new(10_000) { |i| "junk #{i}" }
+
+puts file_work(junk.size)
@@ -0,0 +1,15 @@
+# frozen_string_literal: true
+# An exception raised 50 calls deep: its backtrace is longer than the
+# capture limit test_native_exception_backtrace_limit sets.
+
+def descend(n)
+  raise ArgumentError, 'bottom reached' if n.zero?
+
+  descend(n - 1)
+end
+
+begin
+  descend(50)
+rescue ArgumentError => e
+  puts e.backtrace.size
+end
@@ -0,0 +1,7 @@
+# frozen_string_literal: true
+# An Array nested fifteen levels deep, past the default `max_depth`, used
+# by test_native_capture_limits.
+
+deep_list = [[[[[[[[[[[[[[[:floor]]]]]]]]]]]]]]]
+
+puts deep_list.flatten.size
@@ -0,0 +1,20 @@
+# frozen_string_literal: true
+# Exact numeric types beyond Integer / Float:
+#   - Rational (numerator / denominator)
+#   - Complex (real / imaginary parts)
+#   - BigDecimal (exact decimal digits, no Float rounding)
+
+require 'bigdecimal'
+
+def split_bill(total, people)
+  total / people
+end
+
+share = split_bill(Rational(100, 1), 3)
+root = Complex(3, -4)
+price = BigDecimal('19.990000000000000001')
+sum = price + BigDecimal('0.01')
+
+puts share
+puts root.abs
+puts sum.to_s('F')
@@ -0,0 +1,33 @@
+# frozen_string_literal: true
+# An exception kept in a local after its rescue: a custom error class
+# with its own instance variable and a `cause`.
+
+class ValidationError < StandardError
+  attr_reader :field
+
+  def initialize(field, message)
+    super(message)
+    @field = field
+  end
+end
+
+def validate(value)
+  raise ArgumentError, 'negative' if value.negative?
+
+  value
+end
+
+error = nil
+begin
+  begin
+    validate(-1)
+  rescue ArgumentError
+    raise ValidationError.new(:amount, 'amount must be positive')
+  end
+rescue ValidationError => e
+  error = e
+end
+
+puts error.message
+puts error.field
+puts error.cause.message
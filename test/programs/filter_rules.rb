@@ -0,0 +1,32 @@
+# frozen_string_literal: true
+# A helper class whose frames test_native_filter_rules leaves out with
+# `method:` rules.  `AuditLog#around` stays open on the main thread while
+# another thread runs, whose own lines are still recorded.
+
+class AuditLog
+  def write(entry)
+    entry.upcase
+  end
+
+  def around(&work)
+    Thread.new(&work).join
+  end
+end
+
+class Order
+  def initialize(log)
+    @log = log
+  end
+
+  def place(item)
+    @log.write("placed #{item}")
+    item
+  end
+end
+
+puts Order.new(AuditLog.new).place('book')
+
+AuditLog.new.around do
+  shipped = 'parcel-42'
+  puts shipped
+end
@@ -0,0 +1,33 @@
+# frozen_string_literal: true
+# Hashes recorded as maps:
+#   - plain literal hash with mixed key types
+#   - hash with a default proc (auto-vivifying counter)
+#   - compare_by_identity hash keyed by distinct but equal strings
+#   - a Hash subclass whose `default_proc` raises
+
+class StrictHash < Hash
+  def default_proc
+    raise NotImplementedError, 'no default proc here'
+  end
+end
+
+def tally(words)
+  counts = Hash.new { |h, k| h[k] = 0 }
+  words.each { |w| counts[w] += 1 }
+  counts
+end
+
+config = { name: 'demo', 'retries' => 3, 1 => [true, nil] }
+counts = tally(%w[a b a c a])
+
+by_identity = {}.compare_by_identity
+key_a = +'key'
+key_b = +'key'
+by_identity[key_a] = 1
+by_identity[key_b] = 2
+strict = StrictHash[limit: 10]
+
+puts config.size
+puts counts['a']
+puts by_identity.size
+puts strict[:limit]
@@ -0,0 +1,18 @@
+# frozen_string_literal: true
+# IO handles and enumerators, which must be recorded without reading from
+# the handles or advancing the enumerators.
+
+source = File.open(__FILE__)
+first_line = source.gets
+closed = File.open(__FILE__)
+closed.close
+
+slices = [1, 2, 3].each_slice(2)
+tallies = %i[a b].each_with_object({ a: 1 })
+evens = (1..Float::INFINITY).lazy.map { |n| n * 2 }
+
+puts first_line.start_with?('#')
+puts slices.next.inspect
+puts evens.first(3).inspect
+puts tallies.size
+source.close
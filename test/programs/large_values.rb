@@ -0,0 +1,22 @@
+# frozen_string_literal: true
+# Values larger than the capture limits used by test_native_capture_limits.
+
+require 'set'
+
+numbers = (1..50).to_a
+table = numbers.to_h { |n| [n, n * n] }
+text = 'é' * 40
+legacy_text = ('ü' * 40).encode('ISO-8859-1')
+tags = Set.new(1..30)
+nested = [[[[[:bottom]]]]]
+# Ten levels of Hash: within `max_depth`, but with `--object-ids` and
+# `--max-elements` each level nests four compounds in the encoder.
+deep_table = (1..9).reduce({ leaf: 0 }) { |inner, n| { n => inner, other: n } }
+
+puts numbers.size
+puts table.size
+puts text.bytesize
+puts legacy_text.bytesize
+puts tags.size
+puts nested.flatten.first
+puts deep_table.size
@@ -0,0 +1,27 @@
+# frozen_string_literal: true
+# Named worker threads that hand control back and forth with the main
+# thread, used by test_native_thread_numbers.
+
+to_worker = Queue.new
+to_main = Queue.new
+
+worker = Thread.new do
+  Thread.current.name = 'worker'
+  sum = 0
+  3.times { |i| sum += i }
+  to_main << :ready
+  to_worker.pop
+  Thread.current.name = 'worker again'
+  sum
+end
+
+to_main.pop
+main_steps = 1
+to_worker << :go
+puts worker.value
+
+helper = Thread.new do
+  Thread.current.name = 'helper'
+  main_steps + 1
+end
+puts helper.value
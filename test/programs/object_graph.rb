@@ -0,0 +1,33 @@
+# frozen_string_literal: true
+# Object graphs that a by-value encoder cannot expand:
+#   - a reference cycle A -> B -> C -> A
+#   - an array that contains itself
+#   - one array shared by two variables and twice inside a third
+
+class Node
+  attr_accessor :name, :neighbors
+
+  def initialize(name)
+    @name = name
+    @neighbors = []
+  end
+end
+
+a = Node.new('A')
+b = Node.new('B')
+c = Node.new('C')
+a.neighbors << b
+b.neighbors << c
+c.neighbors << a
+
+itself = [1, 2]
+itself << itself
+
+shared = [1, 2, 3]
+alias_of_shared = shared
+pair = [shared, shared]
+
+puts a.neighbors.first.name
+puts itself.size
+puts alias_of_shared.equal?(shared)
+puts pair.size
@@ -0,0 +1,16 @@
+# frozen_string_literal: true
+# Every parameter kind Method#parameters can report:
+#   - required, optional, splat, required / optional keyword,
+#     double-splat and block parameters
+#   - anonymous `*`, `**` and `&` parameters forwarded to another method
+
+def full_signature(a, b = 2, *rest, key:, opt: 5, **opts, &blk)
+  blk.call(a + b + rest.sum + key + opt + opts.size)
+end
+
+def forwarded(*, **, &)
+  full_signature(*, **, &)
+end
+
+puts full_signature(1, 2, 3, 4, key: 10, extra: true) { |n| n * 2 }
+puts forwarded(1, key: 1) { |n| n + 1 }
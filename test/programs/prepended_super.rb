@@ -0,0 +1,20 @@
+# frozen_string_literal: true
+# A prepended module whose override has a different signature from the
+# method it wraps: the `super` frame must record `Greeter#greet`'s own
+# parameter, not the module's.
+
+module Loud
+  def greet(msg, volume: 1)
+    super(msg) * volume
+  end
+end
+
+class Greeter
+  prepend Loud
+
+  def greet(text)
+    text.upcase
+  end
+end
+
+puts Greeter.new.greet('hello', volume: 2)
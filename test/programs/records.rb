@@ -0,0 +1,29 @@
+# frozen_string_literal: true
+# Record-like values whose fields should be recorded by name:
+#   - Struct members
+#   - Data.define members (Ruby 3.2+)
+#   - plain objects' instance variables, including one assigned lazily
+
+Coordinate = Struct.new(:lat, :lng)
+Money = Data.define(:amount, :currency)
+
+class Book
+  def initialize(title, pages)
+    @title = title
+    @pages = pages
+  end
+
+  def summary
+    @summary ||= "#{@title} (#{@pages} pages)"
+  end
+end
+
+def describe(place, price, book)
+  "#{place.lat},#{place.lng} #{price.amount} #{price.currency} #{book.summary}"
+end
+
+place = Coordinate.new(42.7, 23.3)
+price = Money.new(amount: 15, currency: 'EUR')
+book = Book.new('Dune', 412)
+puts describe(place, price, book)
+puts book.summary
@@ -0,0 +1,31 @@
+# frozen_string_literal: true
+# An exception rescued in an excluded file (swallowing.rb), followed by a
+# method that returns normally, one whose exception a C function rescues
+# and one left by an exception raised in C.
+
+require_relative 'swallowing'
+
+def fail_loudly
+  raise ArgumentError, 'swallowed elsewhere'
+end
+
+def quiet_result
+  nil
+end
+
+def stop_early
+  loop { raise StopIteration }
+end
+
+def divide(count)
+  10 / count
+end
+
+Swallowing.call { fail_loudly }
+quiet_result
+stop_early
+begin
+  divide(0)
+rescue ZeroDivisionError
+  nil
+end
@@ -0,0 +1,42 @@
+# frozen_string_literal: true
+# Loaded by test_native_scoped_recording, which calls `run_scoped` with a
+# recorder: only the calling thread, and only inside `record`, is traced.
+# Leaving a nested `record` keeps the outer one recording.
+
+def traced_work(n)
+  n * 2
+end
+
+def background_work(n)
+  n + 1
+end
+
+def untraced_work(n)
+  n - 1
+end
+
+def nested_work(n)
+  n * 3
+end
+
+def after_nested_work(n)
+  n * 4
+end
+
+def run_scoped(recorder)
+  queue = Queue.new
+  other = Thread.new do
+    queue.pop
+    background_work(1)
+  end
+  untraced_work(1)
+  recorder.record do
+    traced_work(21)
+    recorder.record { nested_work(1) }
+    recorder.record(threads: :all) { nested_work(2) }
+    after_nested_work(1)
+    queue << :go
+    other.join
+  end
+  untraced_work(2)
+end
@@ -0,0 +1,25 @@
+# frozen_string_literal: true
+# Traced by test_native_registered_serializers, which defines `Money`,
+# `Coupon`, `Redeemable` and `Voucher` and registers their serializers
+# before loading this file.
+
+price = Money.new(1999, 'EUR')
+discount = Money.new(500, 'EUR')
+total = Money.new(price.cents - discount.cents, price.currency)
+
+puts total.cents
+
+# `Coupon` gains the `Redeemable` serializer only once it is included.
+coupon = Coupon.new('SAVE5')
+Coupon.include(Redeemable)
+redeemed = Coupon.new('SAVE5')
+
+# The `Voucher` serializer does its work on another thread.
+voucher = Voucher.new('GIFT10')
+
+# A single object gains the `Redeemable` serializer through `extend`.
+Pass = Struct.new(:code)
+plain_pass = Pass.new('VIP')
+vip_pass = Pass.new('VIP').extend(Redeemable)
+
+puts coupon.code, redeemed.code, voucher.code, plain_pass.code, vip_pass.code
@@ -0,0 +1,33 @@
+# frozen_string_literal: true
+# Objects whose methods have side effects.  With --pure-capture the
+# recorder never calls them, so the count printed at the end stays zero.
+
+$to_s_calls = 0
+
+class Noisy
+  def to_s
+    $to_s_calls += 1
+    'noisy'
+  end
+
+  def ping
+    :pong
+  end
+end
+
+class Loud < StandardError
+  def to_s
+    $to_s_calls += 1
+    'loud'
+  end
+end
+
+noisy = Noisy.new
+noisy.ping
+begin
+  raise Loud
+rescue Loud
+  nil
+end
+
+puts "to_s calls: #{$to_s_calls}"
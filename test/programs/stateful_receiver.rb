@@ -0,0 +1,18 @@
+# frozen_string_literal: true
+# A receiver whose state is only visible through `self`: the object is
+# never stored in a local of the traced code.  The String receiver of
+# `upcase` (a C function) is likewise only reachable as `self`.
+
+class Ledger
+  def initialize
+    @audit_trail = []
+  end
+
+  def record(entry)
+    @audit_trail << entry
+    @audit_trail.size
+  end
+end
+
+puts Ledger.new.record('opened')
+puts 'quiet receiver'.upcase
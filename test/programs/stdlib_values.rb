@@ -0,0 +1,21 @@
+# frozen_string_literal: true
+# Standard-library value types with dedicated encodings.
+
+require 'date'
+require 'ipaddr'
+require 'pathname'
+require 'uri'
+
+due = Date.new(2024, 5, 1)
+meeting = DateTime.new(2024, 5, 1, 12, 30, 0, '+02:00')
+deadline = Time.new(2024, 5, 1, 18, 0, 0, '+02:00')
+config = Pathname.new('/etc/app/config.yml')
+endpoint = URI('https://example.com/api?q=1')
+subnet = IPAddr.new('192.168.0.0/24')
+
+puts due.yday
+puts meeting.hour
+puts deadline.utc_offset
+puts config.basename
+puts endpoint.host
+puts subnet.include?(IPAddr.new('192.168.0.7'))
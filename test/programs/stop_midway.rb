@@ -0,0 +1,23 @@
+# frozen_string_literal: true
+# Tracing stopped while two threads are inside methods, used by
+# test_native_frames_closed_per_thread.
+
+def working(started, release)
+  started << true
+  release.pop
+end
+
+def stop_inside(recorder, started)
+  started.pop
+  recorder.stop
+end
+
+def run_stop_midway(recorder)
+  started = Queue.new
+  release = Queue.new
+  recorder.start
+  worker = Thread.new { working(started, release) }
+  stop_inside(recorder, started)
+  release << true
+  worker.join
+end
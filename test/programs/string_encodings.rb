@@ -0,0 +1,13 @@
+# frozen_string_literal: true
+# Strings in encodings other than UTF-8, a binary blob and a UTF-8 string
+# with an invalid byte.
+
+greeting = 'こんにちは'.encode('Shift_JIS')
+latin = 'café'.encode('ISO-8859-1')
+header = [0x89, 0x50, 0x4e, 0x47, 0x00, 0xff].pack('C*')
+broken = "ok\xff"
+
+puts greeting.bytesize
+puts latin.bytesize
+puts header.encoding
+puts broken.valid_encoding?
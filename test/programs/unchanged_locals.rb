@@ -0,0 +1,12 @@
+# frozen_string_literal: true
+# A loop that leaves a large local untouched while a counter changes, used
+# by test_native_incremental_locals.
+
+catalogue = (1..200).map { |n| "item-#{n}" }
+total = 0
+5.times do |i|
+  total += i
+end
+
+puts catalogue.size
+puts total
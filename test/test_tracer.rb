@@ -637,15 +637,11 @@ class TraceTest < Minitest::Test
     # Procs/lambdas surface as different value records depending on the
     # backend.  The pure recorder treats a Proc as a generic Object (no
     # instance variables), so it serialises as Struct{}.  The native
-    # recorder routes the same value through `to_s`, producing
-    # Raw{r="#<Proc:0xADDR path:line>"} — an opaque, address-bearing
-    # string.  The two encodings disagree both in `kind` and in the
-    # presence of a non-deterministic memory address.  Until both
-    # backends agree on a Proc encoding (proposal: a typed
-    # ValueRecord::Closure with source location and arity, no address),
-    # the semantic comparison cannot pass.  See file's program-level
-    # comment for the affected fixture.
-    'blocks_procs_lambdas' => 'Proc/Lambda value encoding diverges between pure (Struct{}) and native (Raw with object address)'
+    # recorder encodes it as a `Proc` struct of (path, line, lambda,
+    # arity) taken from `source_location`.  Until both backends agree on a
+    # Proc encoding the semantic comparison cannot pass.  See file's
+    # program-level comment for the affected fixture.
+    'blocks_procs_lambdas' => 'Proc/Lambda value encoding diverges between pure (Struct{}) and native (Proc source-location struct)'
   }.freeze
 
   Dir.glob(File.join(FIXTURE_DIR, '*_trace.json')).each do |fixture|
@@ -785,6 +781,603 @@ class TraceTest < Minitest::Test
     end
   end
 
+  # Calls are named after the method's owner, not the receiver's class:
+  # class methods use `.`, mixed-in and inherited methods (including
+  # `super` calls) carry the defining module's name.
+  def test_native_method_owner_names
+    skip 'native recorder extension not built' unless native_extension_built?
+    skip 'ct-print not available' unless File.exist?(CT_PRINT)
+
+    names = native_call_function_names('classes')
+    assert_includes names, 'Animal.population'
+    assert_includes names, 'Greeting#greet'
+    assert_includes names, 'Animal#initialize'
+    refute_includes names, 'Class#population'
+    refute_includes names, 'Dog#greet'
+  end
+
+  # A frame entered through `super` from a prepended module records the
+  # parameters of the method actually running, not the module's override.
+  def test_native_prepended_super_parameters
+    skip 'native recorder extension not built' unless native_extension_built?
+    skip 'ct-print not available' unless File.exist?(CT_PRINT)
+
+    bundle = native_ct_print_bundle('prepended_super')
+    entries = bundle['events'].select { |ev| ev['kind'] == 'call_entry' }
+    arg_names = lambda do |function|
+      entries.find { |ev| ev['function'] == function }['args'].map { |arg| arg['varname'] } - ['self']
+    end
+    assert_equal %w[msg volume:], arg_names.call('Loud#greet')
+    assert_equal %w[text], arg_names.call('Greeter#greet')
+  end
+
+  # Call arguments carry their parameter kind in the name (`*rest`,
+  # `key:`, `**opts`, `&blk`), and anonymous splats are kept as bare
+  # sigils instead of being dropped.
+  def test_native_parameter_kinds
+    skip 'native recorder extension not built' unless native_extension_built?
+    skip 'ct-print not available' unless File.exist?(CT_PRINT)
+
+    events = native_ct_print_events('parameter_kinds')
+    ['*rest', 'key:', 'opt:', '**opts', '&blk', '*', '**', '&'].each do |arg_name|
+      assert_includes events, %("#{arg_name}"), "expected call argument `#{arg_name}`"
+    end
+
+    # The block argument is a Proc recorded with where it was written.
+    bundle = native_ct_print_bundle('parameter_kinds')
+    entry = bundle['events'].find do |ev|
+      ev['kind'] == 'call_entry' && ev['function'].end_with?('full_signature')
+    end
+    blk = entry['args'].find { |arg| arg['varname'] == '&blk' }['value']
+    assert_equal 'Struct', blk['kind']
+    path, line = blk['field_values']
+    assert path.values_at('text', 't', 'r').compact.first.end_with?('parameter_kinds.rb'),
+           "expected the block's path in #{path.inspect}"
+    assert_equal 15, line['i']
+  end
+
+  # Bignums used to raise RangeError inside the event hook.  They must be
+  # recorded with every digit intact, sign included.
+  def test_native_bignums
+    skip 'native recorder extension not built' unless native_extension_built?
+    skip 'ct-print not available' unless File.exist?(CT_PRINT)
+
+    events = native_ct_print_events('bignums')
+    assert_includes events, (2**100).to_s
+    assert_includes events, (-2**100).to_s
+    assert_includes events, (1..25).reduce(1, :*).to_s
+  end
+
+  # Rational, Complex and BigDecimal get dedicated tuple types rather
+  # than the generic-object `to_s` fallback.
+  def test_native_exact_numerics
+    skip 'native recorder extension not built' unless native_extension_built?
+    skip 'ct-print not available' unless File.exist?(CT_PRINT)
+
+    events = native_ct_print_events('exact_numerics')
+    %w[Rational Complex BigDecimal].each do |type_name|
+      assert_includes events, %("#{type_name}"), "expected a `#{type_name}` type"
+    end
+    assert_includes events, '19.990000000000000001'
+    assert_includes events, '20.000000000000000001'
+  end
+
+  # Struct members, Data members and instance variables are recorded as
+  # named struct fields (ivars without their `@`).
+  def test_native_named_fields
+    skip 'native recorder extension not built' unless native_extension_built?
+    skip 'ct-print not available' unless File.exist?(CT_PRINT)
+
+    events = native_ct_print_events('records')
+    %w[lat lng amount currency title pages summary].each do |field|
+      assert_includes events, %("#{field}"), "expected a `#{field}` field"
+    end
+    refute_includes events, '"@pages"'
+  end
+
+  # Hashes are recorded as maps; a default proc or identity comparison is
+  # reflected in the type name.
+  def test_native_hash_maps
+    skip 'native recorder extension not built' unless native_extension_built?
+    skip 'ct-print not available' unless File.exist?(CT_PRINT)
+
+    events = native_ct_print_events('hashes')
+    assert_includes events, '"Hash"'
+    assert_includes events, '"tkTableKind"'
+    assert_includes events, '"Hash[default_proc]"'
+    assert_includes events, '"Hash[compare_by_identity]"'
+    # `StrictHash#default_proc` raises; recording carries on without the
+    # flag and the error never reaches the program.
+    refute_includes events, 'no default proc here'
+  end
+
+  # Cycles end in a back-reference at the first repetition; with
+  # `--object-ids` every object also carries its identity.
+  def test_native_object_graph
+    skip 'native recorder extension not built' unless native_extension_built?
+    skip 'ct-print not available' unless File.exist?(CT_PRINT)
+
+    default_events = native_ct_print_events('object_graph')
+    assert_includes default_events, '"<back-reference>"'
+    refute_includes default_events, '"<object>"'
+
+    events = native_ct_print_events('object_graph', '--object-ids')
+    assert_includes events, '"<back-reference>"'
+    assert_includes events, '"<object>"'
+  end
+
+  # `self` of `Noisy#ping` and the raised `Loud` are normally rendered
+  # with their (counting) `to_s`; `--pure-capture` must leave them alone.
+  def test_native_pure_capture
+    skip 'native recorder extension not built' unless native_extension_built?
+    skip 'ct-print not available' unless File.exist?(CT_PRINT)
+
+    default_events = native_ct_print_events('side_effects')
+    refute_includes default_events, 'to_s calls: 0'
+
+    events = native_ct_print_events('side_effects', '--pure-capture')
+    assert_includes events, 'to_s calls: 0'
+  end
+
+  def test_native_capture_limits
+    skip 'native recorder extension not built' unless native_extension_built?
+    skip 'ct-print not available' unless File.exist?(CT_PRINT)
+
+    default_events = native_ct_print_events('large_values')
+    refute_includes default_events, '"<truncated>"'
+    refute_includes default_events, '"<depth-limit>"'
+
+    events = native_ct_print_events('large_values', '--max-elements', '5',
+                                    '--max-string-bytes', '9', '--max-depth', '3')
+    assert_includes events, '"<truncated>"'
+    assert_includes events, '"<depth-limit>"'
+    # The Latin-1 string keeps the four characters that fit in 9 UTF-8 bytes.
+    assert_includes events, 'üüüü'
+    refute_includes events, 'üüüüü'
+
+    # Cut at the encoder's nesting limit rather than at `max_depth`.
+    events = native_ct_print_events('large_values', '--object-ids', '--max-elements', '1')
+    assert_includes events, '"<depth-limit>"'
+
+    # `max_depth` can be raised up to the encoder's nesting limit.
+    default_events = native_ct_print_events('deep_values')
+    assert_includes default_events, '"<depth-limit>"'
+    refute_includes default_events, 'floor'
+    events = native_ct_print_events('deep_values', '--max-depth', '20')
+    refute_includes events, '"<depth-limit>"'
+    assert_includes events, 'floor'
+
+    _stdout, stderr, status = Dir.chdir(File.expand_path('..', __dir__)) do
+      Open3.capture3(RbConfig.ruby, NATIVE_RECORDER_BIN, '--max-depth', '33',
+                     '--out-dir', TMP_DIR, 'test/programs/large_values.rb')
+    end
+    refute status.success?
+    assert_match(/max_depth must be between 1 and 32/, stderr)
+  end
+
+  def test_native_string_encodings
+    skip 'native recorder extension not built' unless native_extension_built?
+    skip 'ct-print not available' unless File.exist?(CT_PRINT)
+
+    events = native_ct_print_events('string_encodings')
+    assert_includes events, '"String[Shift_JIS]"'
+    assert_includes events, '"String[ISO-8859-1]"'
+    assert_includes events, '"Bytes[ASCII-8BIT]"'
+    assert_includes events, '"Bytes[UTF-8]"'
+  end
+
+  def test_native_callables_and_modules
+    skip 'native recorder extension not built' unless native_extension_built?
+    skip 'ct-print not available' unless File.exist?(CT_PRINT)
+
+    events = native_ct_print_events('callables')
+    %w[Proc Method UnboundMethod Class Module Ancestors].each do |type_name|
+      assert_includes events, %("#{type_name}"), "expected a #{type_name} type in #{events[0, 200]}"
+    end
+    assert_includes events, 'callables.rb'
+    assert_includes events, 'BasicObject'
+    # A Method whose `owner` and `source_location` raise is still recorded
+    # and the errors do not reach the program.
+    assert_includes events, '"broken"'
+
+    # `Greeter.ancestors` is cut like any other sequence.
+    events = native_ct_print_events('callables', '--max-elements', '2')
+    assert_includes events, '"<truncated>"'
+    refute_includes events, 'BasicObject'
+  end
+
+  # A rescued exception held in a local is encoded like the `<exception>`
+  # of a raise event: message, backtrace, cause and its own ivars.
+  def test_native_exception_values
+    skip 'native recorder extension not built' unless native_extension_built?
+    skip 'ct-print not available' unless File.exist?(CT_PRINT)
+
+    events = native_ct_print_events('exception_values')
+    assert_includes events, '"ValidationError"'
+    assert_includes events, '"Location"'
+    assert_includes events, '"field"'
+    assert_includes events, 'amount must be positive'
+    assert_includes events, 'negative'
+  end
+
+  def test_native_stdlib_values
+    skip 'native recorder extension not built' unless native_extension_built?
+    skip 'ct-print not available' unless File.exist?(CT_PRINT)
+
+    events = native_ct_print_events('stdlib_values')
+    %w[Date DateTime Pathname URI IPAddr].each do |type_name|
+      assert_includes events, %("#{type_name}"), "expected a #{type_name} value"
+    end
+    assert_includes events, '2024-05-01'
+    assert_includes events, '/etc/app/config.yml'
+    assert_includes events, 'https://example.com/api?q=1'
+    assert_includes events, '192.168.0.0/24'
+  end
+
+  # Path Strings the recorder caches are pinned against compaction; code
+  # evaluated in a loop is recorded without filling the cache.
+  def test_native_path_cache
+    skip 'native recorder extension not built' unless native_extension_built?
+    skip 'ct-print not available' unless File.exist?(CT_PRINT)
+
+    function_names = native_call_function_names('compacting_paths')
+    assert_includes function_names, 'file_work'
+    assert_includes function_names, 'eval_work_2'
+    assert_includes native_ct_print_events('compacting_paths'), 'compacting_paths.rb'
+  end
+
+  def test_native_io_and_enumerators
+    skip 'native recorder extension not built' unless native_extension_built?
+    skip 'ct-print not available' unless File.exist?(CT_PRINT)
+
+    events = native_ct_print_events('io_and_enumerators')
+    assert_includes events, '"File"'
+    assert_includes events, '"closed"'
+    assert_includes events, 'io_and_enumerators.rb'
+    assert_includes events, '"Enumerator"'
+    assert_includes events, '"each_slice"'
+    assert_includes events, '"each_with_object"'
+    assert_includes events, '"Enumerator::Lazy"'
+    # Recording must not advance the enumerators.
+    assert_includes events, '[1, 2]'
+    assert_includes events, '[2, 4, 6]'
+
+    # The receiver is encoded like any value, within the capture limits.
+    events = native_ct_print_events('io_and_enumerators', '--max-elements', '2')
+    assert_includes events, '"Enumerator"'
+    assert_includes events, '"<truncated>"'
+
+    # A read-only File records its position after the first line; the
+    # mode it is read by needs Ruby 3.3.
+    return if RUBY_VERSION < '3.3'
+
+    bundle = native_ct_print_bundle('io_and_enumerators')
+    positions = bundle['events'].filter_map do |ev|
+      next unless ev['kind'] == 'step'
+
+      source = (ev['vars'] || []).find { |var| var['varname'] == 'source' }
+      source && source['value']['field_values']&.last&.dig('i')
+    end
+    first_line = File.open(File.join('test', 'programs', 'io_and_enumerators.rb'), &:gets)
+    assert_includes positions, first_line.bytesize
+  end
+
+  def test_native_structured_self
+    skip 'native recorder extension not built' unless native_extension_built?
+    skip 'ct-print not available' unless File.exist?(CT_PRINT)
+
+    default_events = native_ct_print_events('stateful_receiver')
+    refute_includes default_events, '"audit_trail"'
+
+    events = native_ct_print_events('stateful_receiver', '--structured-self')
+    assert_includes events, '"audit_trail"'
+
+    # C function receivers stay a class name even with --structured-self.
+    events = native_ct_print_events('stateful_receiver', '--c-calls', '--structured-self')
+    assert_includes events, '"audit_trail"'
+    assert_includes events, 'String#upcase'
+    refute_includes events, 'quiet receiver'
+  end
+
+  def test_native_incremental_locals
+    skip 'native recorder extension not built' unless native_extension_built?
+    skip 'ct-print not available' unless File.exist?(CT_PRINT)
+
+    default_events = native_ct_print_events('unchanged_locals')
+    refute_includes default_events, '"<unchanged>"'
+
+    events = native_ct_print_events('unchanged_locals', '--incremental-locals')
+    assert_includes events, '"<unchanged>"'
+    assert_operator events.scan('"item-200"').size, :<, default_events.scan('"item-200"').size
+  end
+
+  # Threads are recorded under small recorder-assigned numbers (the main
+  # thread is 1) and named threads are announced with a log event.
+  def test_native_thread_numbers
+    skip 'native recorder extension not built' unless native_extension_built?
+    skip 'ct-print not available' unless File.exist?(CT_PRINT)
+
+    events = native_ct_print_events('named_threads')
+    assert_match(/thread 2: worker\b/, events)
+    # The worker keeps its number after the main thread ran in between.
+    assert_match(/thread 2: worker again/, events)
+    # The main thread holds 1, so the next thread started gets 3.
+    assert_match(/thread 3: helper/, events)
+    refute_match(/thread 1: /, events)
+  end
+
+  def test_native_filter_rules
+    skip 'native recorder extension not built' unless native_extension_built?
+    skip 'ct-print not available' unless File.exist?(CT_PRINT)
+
+    default_events = native_ct_print_events('filter_rules')
+    assert_includes default_events, 'AuditLog#write'
+
+    events = native_ct_print_events('filter_rules', '--exclude', 'method:AuditLog#*')
+    refute_includes events, 'AuditLog#write'
+    assert_includes events, 'Order#place'
+    # Frames are filtered per thread: the excluded `AuditLog#around` open
+    # on the main thread does not hide the worker thread's lines.
+    assert_includes events, 'parcel-42'
+
+    events = native_ct_print_events('filter_rules', '--exclude', 'method:AuditLog#*',
+                                    '--include', 'method:*#write')
+    assert_includes events, 'AuditLog#write'
+
+    events = native_ct_print_events('filter_rules', '--exclude', 'programs/*.rb')
+    refute_includes events, 'Order#place'
+  end
+
+  # A config file whose top level is not a mapping is reported as such.
+  def test_filter_config_must_be_a_mapping
+    require_relative '../gems/codetracer-ruby-recorder/lib/codetracer_ruby_recorder'
+
+    Dir.mktmpdir do |dir|
+      Dir.chdir(dir) do
+        File.write(CodeTracer::RubyRecorder::CONFIG_FILE, "- vendor/**\n")
+        error = assert_raises(ArgumentError) { CodeTracer::RubyRecorder.with_filter_rules({}) }
+        assert_match(/expected a mapping/, error.message)
+
+        File.write(CodeTracer::RubyRecorder::CONFIG_FILE, '')
+        assert_equal({}, CodeTracer::RubyRecorder.with_filter_rules({}))
+      end
+    end
+  end
+
+  # Serializers are registered through the Ruby API, so the program is
+  # driven by a small script instead of the CLI.
+  def test_native_registered_serializers
+    skip 'native recorder extension not built' unless native_extension_built?
+    skip 'ct-print not available' unless File.exist?(CT_PRINT)
+
+    Dir.chdir(File.expand_path('..', __dir__)) do
+      out_dir = File.join(TMP_DIR, 'serializers_native')
+      FileUtils.rm_rf(out_dir)
+      FileUtils.mkdir_p(out_dir)
+      driver = <<~RUBY
+        require 'codetracer_ruby_recorder'
+        Money = Struct.new(:cents, :currency)
+        recorder = CodeTracer::RubyRecorder.new(#{out_dir.inspect})
+        recorder.register_serializer(Money) { |m| format('%.2f %s', m.cents / 100.0, m.currency) }
+        Coupon = Struct.new(:code)
+        module Redeemable; end
+        recorder.register_serializer(Redeemable) { |c| "redeem \#{c.code}" }
+        Voucher = Struct.new(:code)
+        recorder.register_serializer(Voucher) { |v| Thread.new { v.code.downcase }.value }
+        recorder.start
+        load 'test/programs/serializers.rb'
+        recorder.stop
+        recorder.flush_trace
+      RUBY
+      _stdout, stderr, status = Open3.capture3(
+        RbConfig.ruby, '-I', 'gems/codetracer-ruby-recorder/lib', '-e', driver
+      )
+      assert status.success?, "trace failed: #{stderr}"
+
+      events = ct_print_events(out_dir)
+      assert_includes events, '19.99 EUR'
+      assert_includes events, '14.99 EUR'
+      assert_includes events, 'redeem SAVE5'
+      assert_includes events, 'gift10'
+      assert_includes events, 'redeem VIP'
+    end
+  end
+
+  # Frames still open on several threads when tracing stops are each
+  # closed on their own thread.
+  def test_native_frames_closed_per_thread
+    skip 'native recorder extension not built' unless native_extension_built?
+    skip 'ct-print not available' unless File.exist?(CT_PRINT)
+
+    Dir.chdir(File.expand_path('..', __dir__)) do
+      out_dir = File.join(TMP_DIR, 'stop_midway_native')
+      FileUtils.rm_rf(out_dir)
+      FileUtils.mkdir_p(out_dir)
+      driver = <<~RUBY
+        require 'codetracer_ruby_recorder'
+        load 'test/programs/stop_midway.rb'
+        recorder = CodeTracer::RubyRecorder.new(#{out_dir.inspect})
+        run_stop_midway(recorder)
+        recorder.flush_trace
+      RUBY
+      _stdout, stderr, status = Open3.capture3(
+        RbConfig.ruby, '-I', 'gems/codetracer-ruby-recorder/lib', '-e', driver
+      )
+      assert status.success?, "trace failed: #{stderr}"
+
+      bundle = ct_print_bundle(out_dir)
+      entries = Hash.new(0)
+      exits = Hash.new(0)
+      bundle['events'].each do |ev|
+        entries[ev['function']] += 1 if ev['kind'] == 'call_entry'
+        exits[ev['function']] += 1 if ev['kind'] == 'call_exit'
+      end
+      assert(entries.keys.any? { |function| function.end_with?('working') }, 'no call_entry for working')
+      assert_equal entries, exits
+    end
+  end
+
+  def test_native_scoped_recording
+    skip 'native recorder extension not built' unless native_extension_built?
+    skip 'ct-print not available' unless File.exist?(CT_PRINT)
+
+    Dir.chdir(File.expand_path('..', __dir__)) do
+      out_dir = File.join(TMP_DIR, 'scoped_recording_native')
+      FileUtils.rm_rf(out_dir)
+      FileUtils.mkdir_p(out_dir)
+      driver = <<~RUBY
+        require 'codetracer_ruby_recorder'
+        load 'test/programs/scoped_recording.rb'
+        recorder = CodeTracer::RubyRecorder.new(#{out_dir.inspect})
+        run_scoped(recorder)
+        recorder.flush_trace
+      RUBY
+      _stdout, stderr, status = Open3.capture3(
+        RbConfig.ruby, '-I', 'gems/codetracer-ruby-recorder/lib', '-e', driver
+      )
+      assert status.success?, "trace failed: #{stderr}"
+
+      events = ct_print_events(out_dir)
+      assert_includes events, 'traced_work'
+      assert_includes events, 'nested_work'
+      assert_includes events, 'after_nested_work'
+      refute_includes events, 'background_work'
+      refute_includes events, 'untraced_work'
+    end
+  end
+
+  # `--c-calls` adds calls into C-implemented methods to the call stream.
+  # `array_sum.rb` iterates with `Array#each`, which is a cfunc; without
+  # the flag it must stay invisible so the default trace shape (and the
+  # fixture comparison above) is unchanged.
+  def test_native_c_calls_opt_in
+    skip 'native recorder extension not built' unless native_extension_built?
+    skip 'ct-print not available' unless File.exist?(CT_PRINT)
+
+    default_names = native_call_function_names('array_sum')
+    refute_includes default_names, 'Array#each',
+                    "C calls must not be recorded by default; got #{default_names.inspect}"
+
+    c_call_names = native_call_function_names('array_sum', '--c-calls')
+    assert_includes c_call_names, 'Array#each',
+                    "expected `Array#each` in native call stream with --c-calls; got #{c_call_names.inspect}"
+    assert_includes c_call_names, 'sum'
+  end
+
+  def test_native_blocks_opt_in
+    skip 'native recorder extension not built' unless native_extension_built?
+    skip 'ct-print not available' unless File.exist?(CT_PRINT)
+
+    default_names = native_call_function_names('array_sum')
+    refute(default_names.any? { |n| n.start_with?('block in ') },
+           "blocks must not be recorded by default; got #{default_names.inspect}")
+
+    block_names = native_call_function_names('array_sum', '--blocks')
+    assert_includes block_names, 'block in sum',
+                    "expected `block in sum` in native call stream with --blocks; got #{block_names.inspect}"
+
+    # Each invocation records its block parameter and the block's value.
+    bundle = native_ct_print_bundle('array_sum', '--blocks')
+    block_events = lambda do |kind|
+      bundle['events'].select { |ev| ev['kind'] == kind && ev['function'].end_with?('block in sum') }
+    end
+    params = block_events.call('call_entry').map do |ev|
+      ev['args'].find { |arg| arg['varname'] == 'n' }&.dig('value', 'i')
+    end
+    assert_equal [1, 2, 3], params
+    assert_equal [1, 3, 6], block_events.call('call_exit').map { |ev| ev['return_value']['i'] }
+  end
+
+  def test_native_class_bodies_opt_in
+    skip 'native recorder extension not built' unless native_extension_built?
+    skip 'ct-print not available' unless File.exist?(CT_PRINT)
+
+    default_names = native_call_function_names('classes')
+    refute_includes default_names, '<class:Animal>',
+                    "class bodies must not be recorded by default; got #{default_names.inspect}"
+
+    body_names = native_call_function_names('classes', '--class-bodies')
+    assert_includes body_names, '<module:Greeting>'
+    assert_includes body_names, '<class:Animal>'
+    assert_includes body_names, '<class:Dog>'
+  end
+
+  # `--exceptions` records where each exception is rescued.  The default
+  # trace keeps only the flat error event so it still matches the pure
+  # recorder fixture.
+  def test_native_exceptions_opt_in
+    skip 'native recorder extension not built' unless native_extension_built?
+    skip 'ct-print not available' unless File.exist?(CT_PRINT)
+
+    default_events = native_ct_print_events('exceptions')
+    refute_match(/rescued NotFoundError/, default_events)
+
+    events = native_ct_print_events('exceptions', '--exceptions')
+    assert_match(/rescued NotFoundError: no entry for missing/, events)
+    assert_match(/rescued AppError: inner/, events)
+    assert_includes native_call_function_names('exceptions', '--exceptions'), 'propagate'
+
+    # Frames unwound by an exception are closed, so every exit matches
+    # the innermost open entry and none is left open.
+    bundle = native_ct_print_bundle('exceptions', '--exceptions')
+    open_calls = []
+    bundle['events'].each do |ev|
+      case ev['kind']
+      when 'call_entry'
+        open_calls << ev['function']
+      when 'call_exit'
+        assert_equal open_calls.pop, ev['function'], 'call_exit does not match the open call'
+      end
+    end
+    assert_empty open_calls, "calls left open: #{open_calls.inspect}"
+
+    # The step of the unwound `propagate` return carries the exception.
+    exit_index = bundle['events'].index do |ev|
+      ev['kind'] == 'call_exit' && ev['function'].end_with?('propagate')
+    end
+    refute_nil exit_index, 'no call_exit for propagate'
+    return_step = bundle['events'][0...exit_index].reverse.find { |ev| ev['kind'] == 'step' }
+    refute_nil return_step, 'no step before the propagate call_exit'
+    assert_includes return_step['vars'].map { |v| v['varname'] }, '<exception>'
+  end
+
+  # A rescue ends the unwinding even in a file that is not recorded: only
+  # the frame the exception left carries it, not later normal returns.
+  def test_native_unwinding_ends_at_rescue
+    skip 'native recorder extension not built' unless native_extension_built?
+    skip 'ct-print not available' unless File.exist?(CT_PRINT)
+
+    bundle = native_ct_print_bundle('rescue_elsewhere', '--exceptions',
+                                    '--exclude', 'programs/swallowing.rb')
+    return_vars = lambda do |function|
+      exit_index = bundle['events'].index do |ev|
+        ev['kind'] == 'call_exit' && ev['function'].end_with?(function)
+      end
+      refute_nil exit_index, "no call_exit for #{function}"
+      step = bundle['events'][0...exit_index].reverse.find { |ev| ev['kind'] == 'step' }
+      step['vars'].map { |v| v['varname'] }
+    end
+    assert_includes return_vars.call('fail_loudly'), '<exception>'
+    refute_includes return_vars.call('quiet_result'), '<exception>'
+    # `loop` rescues StopIteration in C, without a rescue event.
+    refute_includes return_vars.call('stop_early'), '<exception>'
+    assert_includes return_vars.call('divide'), '<exception>'
+  end
+
+  # Backtraces are cut to `max_elements` like any other sequence.
+  def test_native_exception_backtrace_limit
+    skip 'native recorder extension not built' unless native_extension_built?
+    skip 'ct-print not available' unless File.exist?(CT_PRINT)
+
+    default_events = native_ct_print_events('deep_raise', '--exceptions')
+    assert_includes default_events, '"Backtrace"'
+    refute_includes default_events, '"<truncated>"'
+
+    events = native_ct_print_events('deep_raise', '--exceptions', '--max-elements', '20')
+    assert_includes events, '"Backtrace"'
+    assert_includes events, '"<truncated>"'
+  end
+
   def test_pure_debug_smoke
     Dir.chdir(File.expand_path('..', __dir__)) do
       env = { 'CODETRACER_RUBY_RECORDER_DEBUG' => '1' }
@@ -1260,6 +1853,69 @@ class TraceTest < Minitest::Test
 
   private
 
+  # Record `test/programs/<base>.rb` with the native recorder (passing any
+  # extra recorder flags) and return the function names of every `call`
+  # event in the produced trace, in stream order.
+  def native_call_function_names(base, *recorder_flags)
+    # See test_native_calltrace_includes_user_methods for why the call
+    # events are scanned rather than parsed.
+    native_ct_print_events(base, *recorder_flags)
+      .scan(/"type":\s*"call",[\s\S]*?"function":\s*"([^"]+)"/).flatten
+  end
+
+  # Record test/programs/<base>.rb with the native recorder and return the
+  # raw `ct-print --json-events` output, forced to binary encoding.
+  def native_ct_print_events(base, *recorder_flags)
+    ct_print_events(native_trace_dir(base, *recorder_flags))
+  end
+
+  # Record test/programs/<base>.rb with the native recorder and return the
+  # decoded `ct-print --full` bundle.
+  def native_ct_print_bundle(base, *recorder_flags)
+    ct_print_bundle(native_trace_dir(base, *recorder_flags))
+  end
+
+  # The decoded `ct-print --full` bundle for the trace recorded into
+  # `out_dir`.
+  def ct_print_bundle(out_dir)
+    ct_files = Dir.glob(File.join(out_dir, '*.ct'))
+    refute_empty ct_files, 'native recorder did not produce a .ct trace'
+
+    stdout, stderr, status = Open3.capture3(CT_PRINT, '--full', '--strip-paths', ct_files.first)
+    assert status.success?, "ct-print --full failed: #{stderr}"
+
+    JSON.parse(stdout)
+  end
+
+  # Record test/programs/<base>.rb with the native recorder and return the
+  # absolute output directory.
+  def native_trace_dir(base, *recorder_flags)
+    Dir.chdir(File.expand_path('..', __dir__)) do
+      out_dir = File.join(TMP_DIR, "#{base}_native_calls#{recorder_flags.join.gsub(/\W/, '_')}")
+      FileUtils.rm_rf(out_dir)
+      FileUtils.mkdir_p(out_dir)
+      program = File.join('test', 'programs', "#{base}.rb")
+      _stdout, stderr, status = Open3.capture3(
+        RbConfig.ruby, NATIVE_RECORDER_BIN, *recorder_flags, '--out-dir', out_dir, program
+      )
+      assert status.success?, "trace failed: #{stderr}"
+
+      File.expand_path(out_dir)
+    end
+  end
+
+  # The `ct-print --json-events` output for the trace recorded into
+  # `out_dir`, forced to binary encoding.
+  def ct_print_events(out_dir)
+    ct_files = Dir.glob(File.join(out_dir, '*.ct'))
+    refute_empty ct_files, 'native recorder did not produce a .ct trace'
+
+    stdout, stderr, status = Open3.capture3(CT_PRINT, '--json-events', ct_files.first)
+    assert status.success?, "ct-print --json-events failed: #{stderr}"
+
+    stdout.force_encoding(Encoding::ASCII_8BIT)
+  end
+
   # The native gem only works when the Rust extension has been compiled.
   # Skip native-recorder tests rather than fail when the build hasn't
   # been run (e.g. on a fresh checkout) — this is a pre-existing test